@@ -0,0 +1,136 @@
+use std::path::PathBuf;
+
+use directory_filter::{MatchMode, GitState, PreviewSource};
+use index::ScanSettings;
+
+pub const USAGE: &'static str = "usage: directory_filter [options] [root...]
+
+Filters the files below the given roots (the current directory by default),
+or the lines piped in on stdin when no roots are given.
+Without --query an interactive picker is opened on the first root and the
+selected paths are printed. Tab toggles the selection of the highlighted
+path, alt-a selects every match, alt-i inverts and alt-d clears the selection.
+
+options:
+    -q, --query <query>     print the matches for the query and exit
+    -m, --mode <mode>       fuzzy (default), glob, regex or exact
+    -l, --limit <count>     print at most this many matches, or send at most
+                            this many per response with --stdio and --server
+    -j, --threads <count>   number of threads used to scan the roots
+    -H, --hidden            include hidden files and directories
+    -I, --no-ignore         don't skip files matched by .gitignore and .ignore
+    -g, --git <state>       only files that are staged, modified or untracked
+    -p, --preview <command> preview with a shell command, {} is the path
+        --no-preview        don't show the picker's preview pane
+        --stdio             speak line delimited JSON on stdin and stdout
+        --server[=socket]   serve JSON-RPC on a unix socket
+        --socket <path>     serve JSON-RPC on this unix socket
+    -h, --help              print this message
+
+exit status is 0 when something matched or was picked, 1 when nothing matched,
+2 on errors and 130 when the picker was cancelled";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Search,
+    Stdio,
+    Server(Option<PathBuf>),
+    Help,
+}
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub command: Command,
+    pub roots: Vec<String>,
+    pub query: Option<String>,
+    pub match_mode: MatchMode,
+    pub limit: Option<usize>,
+    pub scan_settings: ScanSettings,
+    pub preview: Option<PreviewSource>,
+}
+
+impl Options {
+
+    pub fn parse(arguments: &[String]) -> Result<Self, String> {
+        let mut options = Options {
+            command: Command::Search,
+            roots: vec![],
+            query: None,
+            match_mode: MatchMode::Fuzzy,
+            limit: None,
+            scan_settings: ScanSettings::default(),
+            preview: Some(PreviewSource::Contents),
+        };
+        let mut arguments = arguments.iter();
+        while let Some(argument) = arguments.next() {
+            // --name=value is the same as --name value
+            let (name, mut inline_value) = match argument.find('=') {
+                Some(index) if argument.starts_with("--") => (&argument[..index], Some(argument[index + 1..].to_string())),
+                _ => (argument.as_str(), None),
+            };
+            let mut value = |name: &str| -> Result<String, String> {
+                inline_value.take().or_else(|| arguments.next().cloned())
+                    .ok_or(format!("{} needs a value", name))
+            };
+            match name {
+                "-q" | "--query" => { options.query = Some(try!(value(name))); },
+                "-m" | "--mode" => { options.match_mode = try!(parse_match_mode(&try!(value(name)))); },
+                "-l" | "--limit" => { options.limit = Some(try!(parse_count(name, &try!(value(name))))); },
+                "-j" | "--threads" => { options.scan_settings.threads = Some(try!(parse_count(name, &try!(value(name))))); },
+                "-H" | "--hidden" => { options.scan_settings.hidden = true; },
+                "-I" | "--no-ignore" => { options.scan_settings.ignore = false; },
+                "-g" | "--git" => { options.scan_settings.git_state = Some(try!(parse_git_state(&try!(value(name))))); },
+                "-p" | "--preview" => { options.preview = Some(PreviewSource::Command(try!(value(name)))); },
+                "--no-preview" => { options.preview = None; },
+                "--stdio" => { options.command = Command::Stdio; },
+                // the socket has to be attached, `--server ./project` serves
+                // the project on the default socket
+                "--server" => {
+                    let socket = match (inline_value.take(), &options.command) {
+                        (Some(socket), _) => Some(PathBuf::from(socket)),
+                        (None, &Command::Server(ref socket)) => socket.clone(),
+                        (None, _) => None,
+                    };
+                    options.command = Command::Server(socket);
+                },
+                "--socket" => { options.command = Command::Server(Some(PathBuf::from(try!(value(name))))); },
+                "-h" | "--help" => { options.command = Command::Help; },
+                _ if name.starts_with('-') => { return Err(format!("unknown option {}", name)); },
+                _ => { options.roots.push(argument.clone()); }
+            }
+        }
+        Ok(options)
+    }
+
+    // the current directory when no roots were given
+    pub fn roots(&self) -> Vec<String> {
+        if self.roots.is_empty() {
+            vec![String::from("./")]
+        } else {
+            self.roots.clone()
+        }
+    }
+}
+
+//----------- private -------------//
+
+fn parse_match_mode(mode: &str) -> Result<MatchMode, String> {
+    match mode {
+        "fuzzy" => Ok(MatchMode::Fuzzy),
+        "glob" => Ok(MatchMode::Glob),
+        "regex" => Ok(MatchMode::Regex),
+        "exact" => Ok(MatchMode::Exact),
+        _ => Err(format!("unknown mode {}, expected fuzzy, glob, regex or exact", mode)),
+    }
+}
+
+fn parse_git_state(state: &str) -> Result<GitState, String> {
+    GitState::from_name(state).ok_or(format!("unknown git state {}, expected staged, modified or untracked", state))
+}
+
+fn parse_count(name: &str, count: &str) -> Result<usize, String> {
+    match count.parse() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(format!("{} needs a positive number, got {}", name, count)),
+    }
+}
@@ -0,0 +1,76 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::{Sink, Stream};
+
+use directory_filter::{ContinuousFilter, FilteredDirectory, SubscriptionId, LatestResult, Error, Result};
+
+impl ContinuousFilter {
+
+    pub fn query_sink(&self) -> QuerySink {
+        QuerySink { filter: self.clone() }
+    }
+
+    pub fn result_stream(&self) -> Result<ResultStream> {
+        ResultStream::new(self)
+    }
+}
+
+// Feeds queries into the filter's broker. The broker never applies
+// backpressure, so the sink is always ready.
+pub struct QuerySink {
+    filter: ContinuousFilter,
+}
+
+impl Sink<String> for QuerySink {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _context: &mut Context) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, query_string: String) -> Result<()> {
+        self.filter.set_query(query_string);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _context: &mut Context) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _context: &mut Context) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+// Yields the newest result each time it is polled after a change, skipping
+// any results that were superseded in between. The stream ends once the filter
+// has been shut down and joined.
+pub struct ResultStream {
+    filter: ContinuousFilter,
+    subscription_id: SubscriptionId,
+    latest: LatestResult,
+}
+
+impl ResultStream {
+
+    pub fn new(filter: &ContinuousFilter) -> Result<Self> {
+        let (subscription_id, latest) = try!(filter.subscribe_latest());
+        Ok(ResultStream { filter: filter.clone(), subscription_id: subscription_id, latest: latest })
+    }
+}
+
+impl Stream for ResultStream {
+    type Item = Arc<FilteredDirectory>;
+
+    fn poll_next(self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<Arc<FilteredDirectory>>> {
+        self.latest.poll(context.waker())
+    }
+}
+
+impl Drop for ResultStream {
+    fn drop(&mut self) {
+        let _ = self.filter.unsubscribe(self.subscription_id);
+    }
+}
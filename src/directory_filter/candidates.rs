@@ -0,0 +1,84 @@
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use directory_scanner::{Directory, File};
+
+// What a filter matches against: the files of a scanned directory or a list
+// of arbitrary lines, e.g. piped in on stdin.
+#[derive(Clone)]
+pub enum CandidateSource {
+    Directory(Directory),
+    Lines(CandidateList),
+}
+
+impl CandidateSource {
+
+    pub fn len(&self) -> usize {
+        match *self {
+            CandidateSource::Directory(ref directory) => directory.len(),
+            CandidateSource::Lines(ref lines) => lines.len(),
+        }
+    }
+
+    pub fn files(&self) -> Vec<File> {
+        match *self {
+            CandidateSource::Directory(ref directory) => directory.file_contents(),
+            CandidateSource::Lines(ref lines) => lines.files(),
+        }
+    }
+}
+
+// Lines that keep arriving while they are being filtered. Clones share the
+// same list, so a reader can keep pushing while filters read it; the filter
+// has to be told about new lines (FilterCommand::LinesAppended) to pick them
+// up, and then only filters those.
+//
+// Lines are carried as Files so results look the same whichever source they
+// came from, a line is its own path.
+#[derive(Clone)]
+pub struct CandidateList {
+    files: Arc<RwLock<Vec<File>>>,
+    complete: Arc<AtomicBool>,
+}
+
+impl CandidateList {
+
+    pub fn new() -> Self {
+        CandidateList { files: Arc::new(RwLock::new(vec![])), complete: Arc::new(AtomicBool::new(false)) }
+    }
+
+    pub fn push(&self, line: &str) {
+        self.extend(vec![line.to_string()]);
+    }
+
+    pub fn extend(&self, lines: Vec<String>) {
+        let mut files = match self.files.write() {
+            Ok(files) => files,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        files.extend(lines.into_iter().map(|line| File::new(PathBuf::from(line))));
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.read().map(|files| files.len()).unwrap_or(0)
+    }
+
+    pub fn files(&self) -> Vec<File> {
+        self.files_from(0)
+    }
+
+    // the lines pushed after the first `start` ones
+    pub fn files_from(&self, start: usize) -> Vec<File> {
+        self.files.read().map(|files| files.get(start..).map_or(vec![], |files| files.to_vec())).unwrap_or(vec![])
+    }
+
+    // no more lines will be pushed
+    pub fn finish(&self) {
+        self.complete.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.complete.load(Ordering::SeqCst)
+    }
+}
@@ -1,19 +1,27 @@
 use std::sync::{Arc, Mutex, Condvar};
-use std::sync::mpsc::Sender;
+use std::sync::mpsc::{Receiver, Sender};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 
-use regex::Regex;
-use crossbeam;
 use directory_scanner::{Directory, DirectoryEventBroker};
-use directory_filter::{FilteredDirectory, RegexBuilder, FILTER_EVENT_BROKER};
+use directory_filter::{FilteredDirectory, FilterEventBroker, FilterCommand, Shared, Receipt, FilterHandle, Snapshot, Subscribers, SubscriptionId, LatestResult, ResultDelta, MultiSelection, CandidateSource, Query, QueryOptions, CaseMode, Ranking, RankingSignal, FilePredicate, Proximity, Error, Result};
+
+const DIRECTORY_EVENT_POLL_INTERVAL_MS: u64 = 50;
+const FILTER_EVENT_POLL_INTERVAL_MS: u64 = 250;
 
 #[derive(Clone)]
 pub struct ContinuousFilter {
     actual_filter: Arc<Mutex<Filter>>,
+    filtering_in_progress: Arc<AtomicBool>,
+    started: Arc<AtomicBool>,
     done: Arc<AtomicBool>,
-    pub finished_lock: Arc<Mutex<bool>>,
-    pub finished_condvar: Arc<Condvar>,
+    shutdown_signal: Arc<(Mutex<()>, Condvar)>,
+    filter_event_broker: Arc<FilterEventBroker>,
     new_directory_item_event_broker: DirectoryEventBroker,
+    snapshot: Snapshot,
+    subscribers: Subscribers,
+    selection: MultiSelection,
 }
 
 impl ContinuousFilter{
@@ -21,136 +29,460 @@ impl ContinuousFilter{
     pub fn new(directory: Directory,
                filter_match_transmitter: Arc<Mutex<Sender<FilteredDirectory>>>, new_directory_item_event_broker: DirectoryEventBroker) -> Self {
 
-      let actual_filter = Arc::new(Mutex::new(Filter::new(directory, filter_match_transmitter)));
+      ContinuousFilter::build(CandidateSource::Directory(directory), Some(filter_match_transmitter), new_directory_item_event_broker)
+    }
 
-      let finished_lock = Arc::new(Mutex::new(false));
-      let finished_condvar = Arc::new(Condvar::new());
+    // for callers that only read results through snapshots and subscriptions
+    pub fn from_directory(directory: Directory, new_directory_item_event_broker: DirectoryEventBroker) -> Self {
+      ContinuousFilter::build(CandidateSource::Directory(directory), None, new_directory_item_event_broker)
+    }
 
-      ContinuousFilter {
-          actual_filter: actual_filter,
-          done: Arc::new(AtomicBool::new(false)),
-          finished_lock: finished_lock,
-          finished_condvar: finished_condvar,
-          new_directory_item_event_broker: new_directory_item_event_broker,
-      }
+    // new candidates are only picked up on FilterCommand::LinesAppended, sent
+    // by whoever is feeding the source
+    pub fn from_candidates(candidates: CandidateSource, new_directory_item_event_broker: DirectoryEventBroker) -> Self {
+      ContinuousFilter::build(candidates, None, new_directory_item_event_broker)
     }
 
-    pub fn start(&self) { // TODO could this return a FilteredDirectory that gets updated?
+    pub fn event_broker(&self) -> Arc<FilterEventBroker> {
+        self.filter_event_broker.clone()
+    }
 
+    // runs the initial scan and then listens for filter and directory events on
+    // background threads until the returned handle is shut down
+    pub fn start(&self) -> FilterHandle {
         info!("filter scanning started");
-        crossbeam::scope(|scope| {
-
-            // listen for filter change events and then kick off scan
-            let local_filter = self.actual_filter.clone();
-            let done = self.done.clone();
-            scope.spawn(move || {
-                while !done.load(Ordering::Relaxed) {
-                    match FILTER_EVENT_BROKER.recv() {
-                        Ok(filter_string)  => {
-                            info!("Found new filter string: {}", filter_string);
-                            let mut locked_filter = local_filter.lock().unwrap();
-                            locked_filter.rescan(RegexBuilder::new(filter_string).build());
-                        },
-                        Err(_) => {
-                            done.store(true, Ordering::Relaxed);
-                        }
-                    }
-                }
-            });
-
-            // listen for new directory item events and then kick off scan
-            let local_filter = self.actual_filter.clone();
-            let done = self.done.clone();
-            scope.spawn(move || {
-                while !done.load(Ordering::Relaxed) {
-                    match self.new_directory_item_event_broker.recv() {
-                        Ok(_) => {
-                            let mut locked_filter = local_filter.lock().unwrap();
-                            locked_filter.scan();
-                        },
-                        Err(_) => {} // TODO handle this nicer?
-                    }
-                }
-            });
+        self.started.store(true, Ordering::SeqCst);
+        initial_scan(&self.actual_filter);
+        let listeners = vec![self.spawn_filter_event_listener(), self.spawn_directory_event_listener()];
+        FilterHandle::new(self.clone(), listeners)
+    }
 
-            {
-                let mut locked_filter = self.actual_filter.lock().unwrap();
-                // initial scan
-                locked_filter.scan();
-            }
+    // like start but returns straight away, running the initial scan in the
+    // background as well
+    pub fn spawn(self) -> FilterHandle {
+        info!("filter scanning spawned");
+        self.started.store(true, Ordering::SeqCst);
+        let local_filter = self.actual_filter.clone();
+        let listeners = vec![
+            thread::spawn(move || { initial_scan(&local_filter); }),
+            self.spawn_filter_event_listener(),
+            self.spawn_directory_event_listener(),
+        ];
+        FilterHandle::new(self, listeners)
+    }
+
+    pub fn send(&self, command: FilterCommand) {
+        self.filter_event_broker.send(command);
+    }
+
+    pub fn set_query(&self, query_string: String) {
+        self.send(FilterCommand::SetQuery(query_string));
+    }
+
+    // how long a query has to stay unchanged before it is scanned for
+    pub fn set_debounce(&self, debounce: Duration) {
+        self.filter_event_broker.set_debounce(debounce);
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        self.snapshot.clone()
+    }
+
+    pub fn latest(&self) -> Arc<FilteredDirectory> {
+        self.snapshot.latest()
+    }
+
+    pub fn subscribe(&self) -> Result<(SubscriptionId, Receiver<Arc<FilteredDirectory>>)> {
+        self.subscribers.subscribe()
+    }
+
+    pub fn subscribe_deltas(&self) -> Result<(SubscriptionId, Receiver<ResultDelta>)> {
+        self.subscribers.subscribe_deltas(&self.snapshot)
+    }
+
+    pub fn subscribe_callback<F>(&self, callback: F) -> Result<SubscriptionId> where F: Fn(Arc<FilteredDirectory>) + Send + 'static {
+        self.subscribers.subscribe_callback(callback)
+    }
+
+    pub fn subscribe_latest(&self) -> Result<(SubscriptionId, LatestResult)> {
+        self.subscribers.subscribe_latest()
+    }
+
+    pub fn unsubscribe(&self, id: SubscriptionId) -> Result<bool> {
+        self.subscribers.unsubscribe(id)
+    }
+
+    pub fn selection(&self) -> MultiSelection {
+        self.selection.clone()
+    }
+
+    pub fn toggle_selection(&self, path: &str) -> bool {
+        self.selection.toggle(path)
+    }
+
+    pub fn select_all_matches(&self) {
+        self.selection.select_all(&self.latest());
+    }
+
+    pub fn invert_selection(&self) {
+        self.selection.invert(&self.latest());
+    }
+
+    pub fn selected_paths(&self) -> Vec<String> {
+        self.selection.paths()
+    }
+
+    pub fn shutdown(&self) {
+        info!("filter shutting down");
+        self.done.store(true, Ordering::SeqCst);
+        self.filter_event_broker.close();
+        let &(ref lock, ref condvar) = &*self.shutdown_signal;
+        let _guard = lock.lock();
+        condvar.notify_all();
+    }
+
+    pub fn is_shut_down(&self) -> bool {
+        self.done.load(Ordering::SeqCst)
+    }
+
+    pub fn set_query_options(&self, options: QueryOptions) -> Receipt {
+        self.configure(FilterCommand::SetOptions(options))
+    }
+
+    pub fn set_case_mode(&self, case_mode: CaseMode) -> Receipt {
+        self.configure(FilterCommand::SetCaseMode(case_mode))
+    }
+
+    pub fn add_ranking_signal(&self, signal: Arc<dyn RankingSignal>) -> Receipt {
+        self.configure(FilterCommand::AddRankingSignal(Shared(signal)))
+    }
 
-            self.wait_until_finished();
-        });
+    // ranks results by how close they are to the given path, e.g. the file open
+    // in an editor
+    pub fn set_current_path(&self, current_path: Option<String>) -> Receipt {
+        self.configure(FilterCommand::SetCurrentPath(current_path))
     }
 
+    pub fn add_predicate(&self, predicate: Arc<dyn FilePredicate>) -> Receipt {
+        self.configure(FilterCommand::AddPredicate(Shared(predicate)))
+    }
+
+    pub fn remove_predicate(&self, predicate: Arc<dyn FilePredicate>) -> Receipt {
+        self.configure(FilterCommand::RemovePredicate(Shared(predicate)))
+    }
+
+    pub fn clear_predicates(&self) -> Receipt {
+        self.configure(FilterCommand::ClearPredicates)
+    }
+
+    // doesn't wait on the filter lock, which is held for as long as a scan runs
     pub fn is_processing(&self) -> bool {
-        FILTER_EVENT_BROKER.has_pending_events() || self.scanning_in_progress()
+        self.filter_event_broker.has_pending_events() || self.filtering_in_progress.load(Ordering::SeqCst)
+    }
+
+    pub fn close_subscribers(&self) -> Result<()> {
+        self.subscribers.clear()
+    }
+
+    pub fn check_state(&self) -> Result<()> {
+        self.actual_filter.lock().map(|_| ()).map_err(From::from)
     }
 
 
     //------------ private ----------//
 
-    fn wait_until_finished(&self) {
-        let mut finished = self.finished_lock.lock().unwrap();
-        while !*finished {
-            finished = self.finished_condvar.wait(finished).unwrap();
+    // Once started, changes go through the broker like queries do, so they are
+    // applied in the order they were sent. Before that nothing else is
+    // touching the filter and they are applied straight away.
+    fn configure(&self, command: FilterCommand) -> Receipt {
+        if self.started.load(Ordering::SeqCst) {
+            self.filter_event_broker.send_with_receipt(command)
+        } else {
+            Receipt::ready(self.actual_filter.lock().map_err(Error::from).and_then(|mut locked_filter| locked_filter.apply(command)))
         }
-        self.done.store(true, Ordering::Relaxed);
     }
 
-    fn scanning_in_progress(&self) -> bool {
-       self.actual_filter.lock().unwrap().filtering_in_progress.load(Ordering::Relaxed)
+    fn build(candidates: CandidateSource,
+             filter_match_transmitter: Option<Arc<Mutex<Sender<FilteredDirectory>>>>, new_directory_item_event_broker: DirectoryEventBroker) -> Self {
+
+      let snapshot = Snapshot::new(FilteredDirectory::from_candidates(candidates.clone(), Query::new(String::new()), Ranking::new()));
+      let subscribers = Subscribers::new();
+      let selection = MultiSelection::new();
+      let filtering_in_progress = Arc::new(AtomicBool::new(false));
+      let actual_filter = Arc::new(Mutex::new(Filter::new(candidates, filter_match_transmitter, snapshot.clone(), subscribers.clone(), selection.clone(), filtering_in_progress.clone())));
+
+      ContinuousFilter {
+          actual_filter: actual_filter,
+          filtering_in_progress: filtering_in_progress,
+          started: Arc::new(AtomicBool::new(false)),
+          done: Arc::new(AtomicBool::new(false)),
+          shutdown_signal: Arc::new((Mutex::new(()), Condvar::new())),
+          filter_event_broker: Arc::new(FilterEventBroker::new()),
+          new_directory_item_event_broker: new_directory_item_event_broker,
+          snapshot: snapshot,
+          subscribers: subscribers,
+          selection: selection,
+      }
+    }
+
+    // the broker hands out whatever was queued before it was closed, so pending
+    // commands are still applied before the thread stops
+    fn spawn_filter_event_listener(&self) -> JoinHandle<()> {
+        let continuous_filter = self.clone();
+        let broker = self.filter_event_broker.clone();
+        thread::spawn(move || {
+            loop {
+                match broker.recv_timeout(Duration::from_millis(FILTER_EVENT_POLL_INTERVAL_MS)) {
+                    Ok(FilterCommand::Shutdown) => {
+                        continuous_filter.shutdown();
+                    },
+                    Ok(command) => {
+                        info!("Found new filter command: {:?}", command);
+                        let result = continuous_filter.actual_filter.lock().map_err(Error::from).and_then(|mut locked_filter| locked_filter.apply(command));
+                        if let Err(ref error) = result {
+                            error!("Filter failed to apply filter command: {}", error);
+                        }
+                        broker.acknowledge(result);
+                    },
+                    Err(Error::Timeout) => {
+                        // closing the broker wakes us up anyway, this is a fallback
+                        if continuous_filter.done.load(Ordering::SeqCst) && !broker.has_pending_events() {
+                            break;
+                        }
+                    },
+                    Err(Error::BrokerClosed) => { break; },
+                    Err(error) => {
+                        error!("Filter stopped receiving filter events: {}", error);
+                        break;
+                    }
+                }
+            }
+            info!("filter event listener stopped");
+        })
+    }
+
+    fn spawn_directory_event_listener(&self) -> JoinHandle<()> {
+        let local_filter = self.actual_filter.clone();
+        let done = self.done.clone();
+        let shutdown_signal = self.shutdown_signal.clone();
+        let directory_event_broker = self.new_directory_item_event_broker.clone();
+        thread::spawn(move || {
+            let mut stopping = false;
+            while !stopping {
+                stopping = done.load(Ordering::SeqCst);
+                let mut new_items = false;
+                while let Some(_) = directory_event_broker.try_recv() {
+                    new_items = true;
+                }
+                if new_items {
+                    if let Err(error) = local_filter.lock().map_err(Error::from).and_then(|mut locked_filter| locked_filter.scan()) {
+                        error!("Filter failed to scan new directory items: {}", error);
+                    }
+                } else if !stopping {
+                    let &(ref lock, ref condvar) = &*shutdown_signal;
+                    if let Ok(guard) = lock.lock() {
+                        if !done.load(Ordering::SeqCst) {
+                            let _ = condvar.wait_timeout(guard, Duration::from_millis(DIRECTORY_EVENT_POLL_INTERVAL_MS));
+                        }
+                    }
+                }
+            }
+            info!("directory event listener stopped");
+        })
     }
 
 }
 
+fn initial_scan(filter: &Arc<Mutex<Filter>>) {
+    if let Err(error) = filter.lock().map_err(Error::from).and_then(|mut locked_filter| locked_filter.scan()) {
+        error!("Filter failed initial scan: {}", error);
+    }
+}
+
+
 struct Filter {
-    directory: Directory,
-    filter_match_transmitter: Arc<Mutex<Sender<FilteredDirectory>>>,
+    candidates: CandidateSource,
+    filter_match_transmitter: Option<Arc<Mutex<Sender<FilteredDirectory>>>>,
+    subscribers: Subscribers,
+    snapshot: Snapshot,
+    selection: MultiSelection,
     filtered_directory: FilteredDirectory,
-    regex: Regex,
-    pub filtering_in_progress: AtomicBool, // TODO make this private
+    query: Query,
+    ranking: Ranking,
+    predicates: Vec<Arc<dyn FilePredicate>>,
+    proximity: Option<Arc<Proximity>>,
+    paused: bool,
+    stale: bool,
+    filtering_in_progress: Arc<AtomicBool>,
 }
 
 impl Filter {
 
-    pub fn new(directory: Directory, filter_match_transmitter: Arc<Mutex<Sender<FilteredDirectory>>>) -> Self {
+    pub fn new(candidates: CandidateSource, filter_match_transmitter: Option<Arc<Mutex<Sender<FilteredDirectory>>>>, snapshot: Snapshot, subscribers: Subscribers, selection: MultiSelection, filtering_in_progress: Arc<AtomicBool>) -> Self {
 
-      let initial_regex = Regex::new("").unwrap();
-      let filtered_directory = FilteredDirectory::new(directory.clone(), initial_regex.clone());
+      let initial_query = Query::new(String::new());
+      let filtered_directory = FilteredDirectory::from_candidates(candidates.clone(), initial_query.clone(), Ranking::new());
 
       Filter {
-          directory: directory.clone(),
+          candidates: candidates,
           filter_match_transmitter: filter_match_transmitter,
+          subscribers: subscribers,
+          snapshot: snapshot,
+          selection: selection,
           filtered_directory: filtered_directory,
-          regex: initial_regex,
-          filtering_in_progress: AtomicBool::new(false),
+          query: initial_query,
+          ranking: Ranking::new(),
+          predicates: vec![],
+          proximity: None,
+          paused: false,
+          stale: false,
+          filtering_in_progress: filtering_in_progress,
       }
     }
 
-    pub fn scan(&mut self) {
-        self.filtering_in_progress.store(true, Ordering::Relaxed);
+    pub fn apply(&mut self, command: FilterCommand) -> Result<()> {
+        match command {
+            FilterCommand::SetQuery(query_string) => {
+                let new_query = Query::with_options(query_string, self.query.options.clone());
+                self.rescan(new_query)
+            },
+            FilterCommand::SetCaseMode(case_mode) => {
+                let mut options = self.query.options.clone();
+                options.case_mode = case_mode;
+                let new_query = Query::with_options(self.query.as_str().to_string(), options);
+                self.rescan(new_query)
+            },
+            FilterCommand::SetOptions(options) => {
+                let new_query = Query::with_options(self.query.as_str().to_string(), options);
+                self.rescan(new_query)
+            },
+            FilterCommand::AddPredicate(predicate) => {
+                self.predicates.push(predicate.0);
+                self.scan()
+            },
+            FilterCommand::RemovePredicate(predicate) => {
+                self.predicates.retain(|existing| Shared(existing.clone()) != predicate);
+                self.scan()
+            },
+            FilterCommand::ClearPredicates => {
+                self.predicates.clear();
+                self.scan()
+            },
+            FilterCommand::AddRankingSignal(signal) => {
+                self.ranking.add_signal(signal.0);
+                self.scan()
+            },
+            FilterCommand::SetCurrentPath(current_path) => {
+                self.proximity = current_path.map(|path| Arc::new(Proximity::new(&path)));
+                self.scan()
+            },
+            FilterCommand::Pause => {
+                self.paused = true;
+                Ok(())
+            },
+            FilterCommand::Resume => {
+                self.paused = false;
+                if self.stale { self.scan() } else { Ok(()) }
+            },
+            FilterCommand::ForceRescan => self.scan(),
+            FilterCommand::LinesAppended => self.filter_appended(),
+            FilterCommand::Shutdown => Ok(()),
+        }
+    }
+
+    pub fn scan(&mut self) -> Result<()> {
+        if self.paused {
+            self.stale = true;
+            return Ok(());
+        }
+        self.stale = false;
+        self.filtering_in_progress.store(true, Ordering::SeqCst);
+        let result = self.run_scan();
+        self.filtering_in_progress.store(false, Ordering::SeqCst);
+        result
+    }
+
+    // a full scan is still due while paused, it picks the lines up as well
+    pub fn filter_appended(&mut self) -> Result<()> {
+        if self.paused {
+            self.stale = true;
+            return Ok(());
+        }
+        self.filtering_in_progress.store(true, Ordering::SeqCst);
+        let result = self.run_filter_appended();
+        self.filtering_in_progress.store(false, Ordering::SeqCst);
+        result
+    }
+
+    pub fn rescan(&mut self, new_query: Query) -> Result<()> {
+        if self.paused {
+            self.query = new_query;
+            self.stale = true;
+            return Ok(());
+        }
+        self.filtering_in_progress.store(true, Ordering::SeqCst);
+        let result = self.run_rescan(new_query);
+        self.filtering_in_progress.store(false, Ordering::SeqCst);
+        result
+    }
+
+    fn run_scan(&mut self) -> Result<()> {
         info!("Filter scanning");
-        let mut new_filtered_directory = FilteredDirectory::new(self.directory.clone(), self.regex.clone()); // TODO send through an event broker here
-        new_filtered_directory.run_filter();
+        let mut new_filtered_directory = FilteredDirectory::from_candidates(self.candidates.clone(), self.query.clone(), self.ranking())
+            .with_predicates(self.predicates.clone()); // TODO send through an event broker here
+        try!(new_filtered_directory.run_filter());
         // TODO here we can listen for new events from the event broker and merge them to the
         // filtered directory
-        if self.filtered_directory.file_matches != new_filtered_directory.file_matches {
+        let changed = self.filtered_directory.file_matches != new_filtered_directory.file_matches;
+        self.filtered_directory = new_filtered_directory;
+        if changed {
             info!("Filter found matches to be different from previous emitting event");
-            self.filtered_directory = new_filtered_directory;
-            let _ = self.filter_match_transmitter.lock().unwrap().send(self.filtered_directory.clone());
+            try!(self.emit());
+        }
+        Ok(())
+    }
+
+    // appended lines can only add matches, so the count tells whether they did
+    fn run_filter_appended(&mut self) -> Result<()> {
+        let previous_len = self.filtered_directory.len();
+        try!(self.filtered_directory.filter_appended());
+        if self.filtered_directory.len() != previous_len {
+            try!(self.emit());
         }
-        self.filtering_in_progress.store(false, Ordering::Relaxed);
+        Ok(())
     }
 
-    pub fn rescan(&mut self, new_regex: Regex)  {
-        self.filtering_in_progress.store(true, Ordering::Relaxed);
-        info!("Filter rescanning using new regex: {:?}", new_regex);
-        self.regex = new_regex.clone();
-        self.filtered_directory.re_filter(new_regex); // TODO this would also have to list for matches
+    fn run_rescan(&mut self, new_query: Query) -> Result<()> {
+        info!("Filter rescanning using new query: {:?}", new_query);
+        try!(self.filtered_directory.re_filter(new_query.clone())); // TODO this would also have to list for matches
+        self.query = new_query;
         info!("Filter found matches to be different from previous emitting event");
-        let _ = self.filter_match_transmitter.lock().unwrap().send(self.filtered_directory.clone());
-        self.filtering_in_progress.store(false, Ordering::Relaxed);
+        self.emit()
+    }
+
+    fn emit(&self) -> Result<()> {
+        let filtered_directory = self.filtered_directory.clone();
+        let previous = self.snapshot.latest();
+        self.selection.retain_matching(&filtered_directory);
+        self.snapshot.publish(filtered_directory.clone());
+        let latest = self.snapshot.latest();
+        let generation = latest.generation();
+        try!(self.subscribers.publish(&previous, latest, generation));
+        match self.filter_match_transmitter {
+            Some(ref filter_match_transmitter) => {
+                let transmitter = try!(filter_match_transmitter.lock());
+                transmitter.send(filtered_directory).map_err(|_| Error::SubscriberDisconnected)
+            },
+            None => Ok(()),
+        }
+    }
+
+    fn ranking(&self) -> Ranking {
+        let mut ranking = self.ranking.clone();
+        if let Some(ref proximity) = self.proximity {
+            ranking.add_signal(proximity.clone());
+        }
+        ranking
     }
 
 }
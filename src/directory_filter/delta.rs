@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+
+use directory_scanner::File;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeltaOperation {
+    // a run of files removed from the previous generation
+    Removed { start: usize, len: usize },
+    // a run of files new in this generation, at their final position
+    Added { start: usize, files: Vec<File> },
+    // the files that moved, replacing the range they now occupy
+    Reordered { start: usize, files: Vec<File> },
+}
+
+// The changes between two successive result generations. Applying the
+// operations in order to the previous generation's files gives the current
+// generation's files.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResultDelta {
+    pub previous_generation: usize,
+    pub generation: usize,
+    pub total_len: usize,
+    pub operations: Vec<DeltaOperation>,
+}
+
+impl ResultDelta {
+
+    // Paths are counted rather than collected into sets so repeated paths, e.g.
+    // the same line piped in twice, are matched up one occurrence at a time.
+    // Only the runs of files that ended up out of place are sent as
+    // reorderings, and when the operations would carry more files than the
+    // current generation has it is sent whole instead.
+    pub fn between(previous: &[File], current: &[File], previous_generation: usize, generation: usize) -> Self {
+        let previous_paths: Vec<String> = previous.iter().map(|file| file.as_string()).collect();
+        let current_paths: Vec<String> = current.iter().map(|file| file.as_string()).collect();
+
+        let mut unmatched_current = path_counts(&current_paths);
+        let kept: Vec<bool> = previous_paths.iter().map(|path| take_one(&mut unmatched_current, path)).collect();
+        let mut unmatched_previous = path_counts(&previous_paths);
+        let added: Vec<bool> = current_paths.iter().map(|path| !take_one(&mut unmatched_previous, path)).collect();
+
+        // removals go back to front so earlier indices stay valid
+        let mut operations = vec![];
+        for &(start, end) in runs(kept.iter().map(|kept| !kept)).iter().rev() {
+            operations.push(DeltaOperation::Removed { start: start, len: end - start });
+        }
+        let added_runs = runs(added.iter().cloned());
+        for &(start, end) in added_runs.iter() {
+            operations.push(DeltaOperation::Added { start: start, files: current[start..end].to_vec() });
+        }
+
+        // what the list looks like after the removals and additions, the kept
+        // files in their previous order around the added ones
+        let mut kept_paths = previous_paths.iter().zip(kept.iter()).filter(|&(_, kept)| *kept).map(|(path, _)| path);
+        let misplaced: Vec<bool> = current_paths.iter().zip(added.iter()).map(|(path, added)| {
+            !added && kept_paths.next() != Some(path)
+        }).collect();
+        let misplaced_runs = runs(misplaced.into_iter());
+        for &(start, end) in misplaced_runs.iter() {
+            operations.push(DeltaOperation::Reordered { start: start, files: current[start..end].to_vec() });
+        }
+
+        let carried: usize = added_runs.iter().chain(misplaced_runs.iter()).map(|&(start, end)| end - start).sum();
+        if carried > current.len() {
+            operations = vec![
+                DeltaOperation::Removed { start: 0, len: previous.len() },
+                DeltaOperation::Added { start: 0, files: current.to_vec() },
+            ];
+        }
+
+        ResultDelta {
+            previous_generation: previous_generation,
+            generation: generation,
+            total_len: current.len(),
+            operations: operations,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    pub fn apply(&self, files: &mut Vec<File>) {
+        for operation in self.operations.iter() {
+            match *operation {
+                DeltaOperation::Removed { start, len } => {
+                    files.drain(start..start + len);
+                },
+                DeltaOperation::Added { start, files: ref added } => {
+                    files.splice(start..start, added.iter().cloned());
+                },
+                DeltaOperation::Reordered { start, files: ref reordered } => {
+                    for (offset, file) in reordered.iter().enumerate() {
+                        files[start + offset] = file.clone();
+                    }
+                },
+            }
+        }
+    }
+}
+
+//----------- private -------------//
+
+fn path_counts(paths: &[String]) -> HashMap<&str, usize> {
+    let mut counts = HashMap::new();
+    for path in paths {
+        *counts.entry(path.as_str()).or_insert(0) += 1;
+    }
+    counts
+}
+
+// whether an occurrence of the path was left to match up with
+fn take_one(counts: &mut HashMap<&str, usize>, path: &str) -> bool {
+    match counts.get_mut(path) {
+        Some(count) if *count > 0 => {
+            *count -= 1;
+            true
+        },
+        _ => false,
+    }
+}
+
+// [start, end) ranges of consecutive true flags
+fn runs<I>(flags: I) -> Vec<(usize, usize)> where I: Iterator<Item = bool> {
+    let mut runs = vec![];
+    let mut run_start = None;
+    let mut length = 0;
+    for (index, flag) in flags.enumerate() {
+        match (flag, run_start) {
+            (true, None) => { run_start = Some(index); },
+            (false, Some(start)) => {
+                runs.push((start, index));
+                run_start = None;
+            },
+            _ => {}
+        }
+        length = index + 1;
+    }
+    if let Some(start) = run_start {
+        runs.push((start, length));
+    }
+    runs
+}
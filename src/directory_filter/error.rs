@@ -0,0 +1,61 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::result;
+use std::sync::PoisonError;
+
+use regex;
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidQuery(String),
+    BrokerClosed,
+    SubscriberDisconnected,
+    PoisonedState,
+    ListenerPanicked,
+    Timeout,
+    Io(io::Error),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidQuery(ref reason) => write!(formatter, "invalid query: {}", reason),
+            Error::BrokerClosed => write!(formatter, "filter event broker is no longer receiving events"),
+            Error::SubscriberDisconnected => write!(formatter, "filter match subscriber has disconnected"),
+            Error::PoisonedState => write!(formatter, "filter state was poisoned by a panicked thread"),
+            Error::ListenerPanicked => write!(formatter, "a filter listener thread panicked"),
+            Error::Timeout => write!(formatter, "timed out waiting for a filter event"),
+            Error::Io(ref error) => write!(formatter, "io error: {}", error),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<regex::Error> for Error {
+    fn from(error: regex::Error) -> Self {
+        Error::InvalidQuery(error.to_string())
+    }
+}
+
+impl<T> From<PoisonError<T>> for Error {
+    fn from(_: PoisonError<T>) -> Self {
+        Error::PoisonedState
+    }
+}
@@ -0,0 +1,82 @@
+use std::fmt;
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Receiver, Sender, RecvTimeoutError};
+use std::time::Duration;
+
+use directory_filter::{CaseMode, QueryOptions, FilePredicate, RankingSignal, Error, Result};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FilterCommand {
+    SetQuery(String),
+    SetCaseMode(CaseMode),
+    SetOptions(QueryOptions),
+    AddPredicate(Shared<dyn FilePredicate>),
+    // removes the very predicate that was added, leaving the others in place
+    RemovePredicate(Shared<dyn FilePredicate>),
+    // removes every predicate, including the ones hiding hidden and ignored files
+    ClearPredicates,
+    AddRankingSignal(Shared<dyn RankingSignal>),
+    // ranks results by how close they are to the path, None stops doing so
+    SetCurrentPath(Option<String>),
+    // stop scanning until resumed, remembering anything that would need a rescan
+    Pause,
+    Resume,
+    ForceRescan,
+    // lines were pushed to the candidate list, only they need filtering
+    LinesAppended,
+    Shutdown,
+}
+
+// Predicates and ranking signals can't be compared or printed, so commands
+// carrying one are only equal when they carry the very same one.
+pub struct Shared<T: ?Sized>(pub Arc<T>);
+
+impl<T: ?Sized> Clone for Shared<T> {
+
+    fn clone(&self) -> Self {
+        Shared(self.0.clone())
+    }
+}
+
+impl<T: ?Sized> PartialEq for Shared<T> {
+
+    fn eq(&self, other: &Self) -> bool {
+        Arc::as_ptr(&self.0) as *const u8 == Arc::as_ptr(&other.0) as *const u8
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for Shared<T> {
+
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "Shared({:p})", Arc::as_ptr(&self.0) as *const u8)
+    }
+}
+
+// Resolves to the outcome of a filter change once the filter has applied it,
+// so a change sent while the filter is running can still report its error.
+pub struct Receipt(Receiver<Result<()>>);
+
+impl Receipt {
+
+    pub fn new() -> (Sender<Result<()>>, Self) {
+        let (transmitter, receiver) = channel();
+        (transmitter, Receipt(receiver))
+    }
+
+    pub fn ready(result: Result<()>) -> Self {
+        let (transmitter, receipt) = Receipt::new();
+        let _ = transmitter.send(result);
+        receipt
+    }
+
+    pub fn wait(self) -> Result<()> {
+        self.0.recv().unwrap_or(Err(Error::BrokerClosed))
+    }
+
+    pub fn wait_timeout(self, timeout: Duration) -> Result<()> {
+        self.0.recv_timeout(timeout).unwrap_or_else(|error| match error {
+            RecvTimeoutError::Timeout => Err(Error::Timeout),
+            RecvTimeoutError::Disconnected => Err(Error::BrokerClosed),
+        })
+    }
+}
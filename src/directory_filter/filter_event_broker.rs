@@ -1,88 +1,226 @@
-use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicBool, Ordering, AtomicUsize};
-use crossbeam::sync::MsQueue;
-use std::sync::Condvar;
-
-lazy_static! {
-    pub static ref FILTER_EVENT_BROKER: FilterEventBroker = {
-        FilterEventBroker::new()
-    };
-}
+use std::collections::VecDeque;
+use std::sync::{Mutex, MutexGuard, Condvar};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::{Duration, Instant};
 
+use directory_filter::{FilterCommand, Receipt, Error, Result};
 
+// Queues filter commands for the filter's listener thread.
+//
+// Consecutive queries are coalesced so only the latest one is scanned for, and
+// with a debounce window set a query is only handed out once no newer query
+// has arrived for that long. All other commands are delivered in order.
+// Commands sent with a receipt are answered once the receiver acknowledges
+// them.
 pub struct FilterEventBroker {
-    events: Arc<MsQueue<String>>,
-    receiving_events: AtomicBool,
-    mutex: Mutex<bool>,
+    state: Mutex<BrokerState>,
     condvar: Condvar,
-    pending_events: AtomicUsize,
+}
+
+struct BrokerState {
+    events: VecDeque<(FilterCommand, Option<Sender<Result<()>>>)>,
+    // the receipt of the command handed out last, until it is acknowledged
+    in_flight: Option<Sender<Result<()>>>,
+    receiving_events: bool,
+    debounce: Duration,
+    last_query_at: Option<Instant>,
+    ready_transmitters: Vec<Sender<()>>,
+}
+
+enum NextEvent {
+    Ready(FilterCommand),
+    Wait(Option<Duration>),
+    Closed,
 }
 
 impl FilterEventBroker {
 
     pub fn new() -> Self {
+        FilterEventBroker::with_debounce(Duration::from_millis(0))
+    }
+
+    pub fn with_debounce(debounce: Duration) -> Self {
         FilterEventBroker {
-            events: Arc::new(MsQueue::new()),
-            receiving_events: AtomicBool::new(true),
+            state: Mutex::new(BrokerState {
+                events: VecDeque::new(),
+                in_flight: None,
+                receiving_events: true,
+                debounce: debounce,
+                last_query_at: None,
+                ready_transmitters: vec![],
+            }),
             condvar: Condvar::new(),
-            mutex: Mutex::new(false),
-            pending_events: AtomicUsize::new(0),
         }
     }
 
-    pub fn send(&self, filter_event: String) {
-        self.events.push(filter_event);
-        self.condvar.notify_one();
-        self.pending_events.fetch_add(1, Ordering::Relaxed);
+    pub fn set_debounce(&self, debounce: Duration) {
+        self.lock_state().debounce = debounce;
+        self.condvar.notify_all();
+    }
+
+    pub fn send(&self, filter_event: FilterCommand) {
+        self.enqueue(filter_event, None);
+    }
+
+    // the receipt resolves to BrokerClosed when the command is dropped instead
+    pub fn send_with_receipt(&self, filter_event: FilterCommand) -> Receipt {
+        let (transmitter, receipt) = Receipt::new();
+        self.enqueue(filter_event, Some(transmitter));
+        receipt
+    }
+
+    // answers the receipt of the command handed out last, if it had one
+    pub fn acknowledge(&self, result: Result<()>) {
+        if let Some(transmitter) = self.lock_state().in_flight.take() {
+            let _ = transmitter.send(result);
+        }
     }
 
+    // anything already queued is still handed out after closing, without
+    // waiting for the debounce window
     pub fn close(&self) {
-        self.receiving_events.store(false, Ordering::Relaxed);
+        let mut state = self.lock_state();
+        state.receiving_events = false;
+        state.signal_ready();
         self.condvar.notify_all();
     }
 
-    pub fn try_recv(&self) -> Option<String> {
-        let mut return_value = None;
-        let mut done = false;
-        while !done {
-            match self.events.try_pop() {
-                Some(event) => {
-                    return_value = Some(event);
-                    self.pending_events.fetch_sub(1, Ordering::Relaxed);
-                },
-                None  => { done = true; }
+    pub fn try_recv(&self) -> Option<FilterCommand> {
+        match self.lock_state().next_event() {
+            NextEvent::Ready(event) => Some(event),
+            _ => None,
+        }
+    }
+
+    pub fn recv(&self) -> Result<FilterCommand>  {
+        self.recv_until(None)
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<FilterCommand> {
+        self.recv_until(Some(Instant::now() + timeout))
+    }
+
+    pub fn recv_deadline(&self, deadline: Instant) -> Result<FilterCommand> {
+        self.recv_until(Some(deadline))
+    }
+
+    pub fn has_pending_events(&self) -> bool {
+        !self.lock_state().events.is_empty()
+    }
+
+    // Some(zero) when try_recv would return a command right now, the time left
+    // on the debounce window when a query is waiting, and None when there is
+    // nothing queued
+    pub fn next_ready_in(&self) -> Option<Duration> {
+        self.lock_state().ready_in()
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.next_ready_in() == Some(Duration::from_millis(0))
+    }
+
+    // Receives a unit every time a command is sent or the broker is closed, so
+    // other event loops can wait on the broker alongside their own sources and
+    // then call try_recv. A debounced query may need polling again after
+    // next_ready_in.
+    pub fn ready_signal(&self) -> Receiver<()> {
+        let (transmitter, receiver) = channel();
+        self.lock_state().ready_transmitters.push(transmitter);
+        receiver
+    }
+
+    //----------- private -------------//
+
+    fn enqueue(&self, filter_event: FilterCommand, receipt: Option<Sender<Result<()>>>) {
+        let mut state = self.lock_state();
+        if !state.receiving_events {
+            warn!("Dropping filter command sent after the broker was closed: {:?}", filter_event);
+            return;
+        }
+        if let FilterCommand::SetQuery(_) = filter_event {
+            state.last_query_at = Some(Instant::now());
+            if let Some(&(FilterCommand::SetQuery(_), _)) = state.events.back() {
+                state.events.pop_back();
             }
         }
-        return_value
-    }
-
-    pub fn recv(&self) -> Result<String, &str>  {
-        let mut return_event = Err("I dont't know");
-        match self.try_recv() {
-            Some(event) => { return_event = Ok(event); }
-            None => {
-                let mut done = false;
-                while !done {
-                    let mutex_guard = self.mutex.lock().unwrap();
-                    let _ = self.condvar.wait(mutex_guard).unwrap();
-                    if !self.receiving_events.load(Ordering::Relaxed) {
-                        done = true;
-                        return Err("no longer receiving events"); //TODO send a real error type
-                    }
-                    match self.try_recv() {
-                        Some(event) =>  {
-                            done = true;
-                            return_event = Ok(event);
-                        },
-                        None => {}
+        state.events.push_back((filter_event, receipt));
+        state.signal_ready();
+        self.condvar.notify_all();
+    }
+
+    fn recv_until(&self, deadline: Option<Instant>) -> Result<FilterCommand> {
+        let mut state = self.lock_state();
+        loop {
+            let wait = match state.next_event() {
+                NextEvent::Ready(event) => { return Ok(event); },
+                NextEvent::Closed => { return Err(Error::BrokerClosed); },
+                NextEvent::Wait(wait) => wait,
+            };
+            let remaining = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(Error::Timeout);
                     }
+                    Some(deadline - now)
+                },
+                None => None,
+            };
+            state = match (wait, remaining) {
+                (None, None) => try!(self.condvar.wait(state)),
+                (Some(wait), Some(remaining)) => try!(self.condvar.wait_timeout(state, wait.min(remaining))).0,
+                (Some(timeout), None) | (None, Some(timeout)) => try!(self.condvar.wait_timeout(state, timeout)).0,
+            };
+        }
+    }
+
+    // the state is only ever a queue and some flags, so it is still usable
+    // after a sender panicked while holding the lock
+    fn lock_state(&self) -> MutexGuard<BrokerState> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl BrokerState {
+
+    fn next_event(&mut self) -> NextEvent {
+        match self.ready_in() {
+            None if self.receiving_events => NextEvent::Wait(None),
+            None => NextEvent::Closed,
+            Some(wait) if wait > Duration::from_millis(0) => NextEvent::Wait(Some(wait)),
+            Some(_) => {
+                match self.events.pop_front() {
+                    Some((event, receipt)) => {
+                        self.in_flight = receipt;
+                        NextEvent::Ready(event)
+                    },
+                    None => NextEvent::Wait(None),
                 }
             }
         }
-        return_event
     }
 
-    pub fn has_pending_events(&self) -> bool {
-        self.pending_events.load(Ordering::Relaxed) > 0
+    fn ready_in(&self) -> Option<Duration> {
+        let debouncing = match self.events.front() {
+            None => { return None; },
+            Some(&(FilterCommand::SetQuery(_), _)) => self.receiving_events,
+            Some(_) => false,
+        };
+        if debouncing {
+            if let Some(last_query_at) = self.last_query_at {
+                let elapsed = last_query_at.elapsed();
+                if elapsed < self.debounce {
+                    return Some(self.debounce - elapsed);
+                }
+            }
+        }
+        Some(Duration::from_millis(0))
+    }
+
+    fn signal_ready(&mut self) {
+        self.ready_transmitters.retain(|transmitter| transmitter.send(()).is_ok());
     }
 }
@@ -0,0 +1,102 @@
+use std::sync::Arc;
+use std::sync::mpsc::Receiver;
+use std::thread::JoinHandle;
+
+use directory_filter::{ContinuousFilter, FilterCommand, FilteredDirectory, Snapshot, SubscriptionId, LatestResult, ResultDelta, MultiSelection, Error, Result};
+
+pub struct FilterHandle {
+    filter: ContinuousFilter,
+    listeners: Vec<JoinHandle<()>>,
+}
+
+impl FilterHandle {
+
+    pub fn new(filter: ContinuousFilter, listeners: Vec<JoinHandle<()>>) -> Self {
+        FilterHandle { filter: filter, listeners: listeners }
+    }
+
+    pub fn send(&self, command: FilterCommand) {
+        self.filter.send(command);
+    }
+
+    pub fn set_query(&self, query_string: String) {
+        self.filter.set_query(query_string);
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        self.filter.snapshot()
+    }
+
+    pub fn latest(&self) -> Arc<FilteredDirectory> {
+        self.filter.latest()
+    }
+
+    pub fn subscribe(&self) -> Result<(SubscriptionId, Receiver<Arc<FilteredDirectory>>)> {
+        self.filter.subscribe()
+    }
+
+    pub fn subscribe_deltas(&self) -> Result<(SubscriptionId, Receiver<ResultDelta>)> {
+        self.filter.subscribe_deltas()
+    }
+
+    pub fn subscribe_callback<F>(&self, callback: F) -> Result<SubscriptionId> where F: Fn(Arc<FilteredDirectory>) + Send + 'static {
+        self.filter.subscribe_callback(callback)
+    }
+
+    pub fn subscribe_latest(&self) -> Result<(SubscriptionId, LatestResult)> {
+        self.filter.subscribe_latest()
+    }
+
+    pub fn unsubscribe(&self, id: SubscriptionId) -> Result<bool> {
+        self.filter.unsubscribe(id)
+    }
+
+    pub fn selection(&self) -> MultiSelection {
+        self.filter.selection()
+    }
+
+    pub fn toggle_selection(&self, path: &str) -> bool {
+        self.filter.toggle_selection(path)
+    }
+
+    pub fn select_all_matches(&self) {
+        self.filter.select_all_matches();
+    }
+
+    pub fn invert_selection(&self) {
+        self.filter.invert_selection();
+    }
+
+    pub fn selected_paths(&self) -> Vec<String> {
+        self.filter.selected_paths()
+    }
+
+    pub fn filter(&self) -> &ContinuousFilter {
+        &self.filter
+    }
+
+    // asks the listener threads to finish the work already queued and stop
+    pub fn shutdown(&self) {
+        self.filter.shutdown();
+    }
+
+    pub fn is_processing(&self) -> bool {
+        self.filter.is_processing()
+    }
+
+    // blocks until the background threads have stopped, erroring if any of them
+    // panicked or left the filter state poisoned
+    pub fn join(self) -> Result<()> {
+        let mut clean = true;
+        for listener in self.listeners {
+            if listener.join().is_err() {
+                clean = false;
+            }
+        }
+        try!(self.filter.close_subscribers());
+        if !clean {
+            return Err(Error::ListenerPanicked);
+        }
+        self.filter.check_state()
+    }
+}
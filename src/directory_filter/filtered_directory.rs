@@ -1,67 +1,216 @@
 use directory_scanner::{Directory, File};
-use regex::Regex;
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use directory_filter::{Query, Ranking, Score, FilePredicate, CandidateSource, Result};
 use directory_filter::matchers::*;
 
 #[derive(Clone)]
 pub struct FilteredDirectory {
-    directory: Directory,
-    regex: Regex,
+    candidates: CandidateSource,
+    query: Query,
+    ranking: Ranking,
+    predicates: Vec<Arc<dyn FilePredicate>>,
+    // kept from the last filter run so scoring the matches again for display
+    // doesn't compile the query every time
+    matcher: Option<QueryMatcher>,
+    // the typos and score each match was sorted by, and how many candidates
+    // the matches were found among, so lines appended later can be merged in
+    ranks: Vec<(usize, i64)>,
+    filtered_len: usize,
+    generation: usize,
     pub file_matches: Vec<File>,
 }
 
 impl FilteredDirectory {
 
-    pub fn new(directory: Directory, regex: Regex) -> Self {
+    pub fn new(directory: Directory, query: Query) -> Self {
+        FilteredDirectory::with_ranking(directory, query, Ranking::new())
+    }
+
+    pub fn with_ranking(directory: Directory, query: Query, ranking: Ranking) -> Self {
+        FilteredDirectory::from_candidates(CandidateSource::Directory(directory), query, ranking)
+    }
+
+    pub fn from_candidates(candidates: CandidateSource, query: Query, ranking: Ranking) -> Self {
       FilteredDirectory {
-           directory: directory,
-           regex: regex,
+           candidates: candidates,
+           query: query,
+           ranking: ranking,
+           predicates: vec![],
+           matcher: None,
+           ranks: vec![],
+           filtered_len: 0,
+           generation: 0,
            file_matches: vec![],
       }
     }
 
+    pub fn with_predicates(mut self, predicates: Vec<Arc<dyn FilePredicate>>) -> Self {
+        self.predicates = predicates;
+        self
+    }
+
+    pub fn query(&self) -> &Query {
+        &self.query
+    }
+
+    // set when the result is published, see Snapshot::generation
+    pub fn with_generation(mut self, generation: usize) -> Self {
+        self.generation = generation;
+        self
+    }
+
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
     pub fn len(&self) -> usize {
         self.file_matches.len()
     }
 
     pub fn total_len(&self) -> usize {
-        self.directory.len()
+        self.candidates.len()
+    }
+
+    pub fn score(&self, file: &File) -> Option<Score> {
+        match self.matcher {
+            Some(ref matcher) => matcher.score(&file.as_string()),
+            None => self.build_matcher(self.query.clone()).ok().and_then(|matcher| matcher.score(&file.as_string())),
+        }
     }
 
-    pub fn run_filter(&mut self) {
-        info!("Running filter with {:?}", self.regex);
-        if self.regex.to_string() == "" || self.regex.to_string() == ".*" {
-            info!("Match all filter found, returning all files");
-            self.file_matches = self.directory.file_contents();
+    pub fn run_filter(&mut self) -> Result<()> {
+        info!("Running filter with {:?}", self.query);
+        self.matcher = None;
+        let matcher = try!(self.build_matcher(self.query.clone()));
+        if self.query.is_match_all() {
+            info!("Match all filter found, returning all accepted files");
+            let files = self.candidates.files();
+            self.filtered_len = files.len();
+            self.file_matches = files.into_iter().filter(|file| matcher.accepts(&file.as_string())).collect();
         } else {
-            self.file_matches = find_matches(&self.directory, self.regex.clone());
+            self.file_matches = match self.candidates {
+                CandidateSource::Directory(ref directory) => {
+                    self.filtered_len = directory.len();
+                    find_matches(directory, &matcher)
+                },
+                CandidateSource::Lines(ref lines) => {
+                    let files = lines.files();
+                    self.filtered_len = files.len();
+                    find_file_matches(&files, &matcher)
+                },
+            };
             info!("Filter found {} matches", self.len());
         }
+        self.sort_matches(&matcher);
+        self.matcher = Some(matcher);
+        Ok(())
+    }
+
+    // Filters only the lines appended since the last run and merges their
+    // matches into the ones already found. Directories are filtered again
+    // whole.
+    pub fn filter_appended(&mut self) -> Result<()> {
+        let (appended, matcher) = match (&self.candidates, &self.matcher) {
+            (&CandidateSource::Lines(ref lines), &Some(ref matcher)) if self.ranks.len() == self.file_matches.len() => {
+                (lines.files_from(self.filtered_len), matcher.clone())
+            },
+            _ => { return self.run_filter(); },
+        };
+        self.filtered_len += appended.len();
+        let new_matches = if self.query.is_match_all() {
+            appended.into_iter().filter(|file| matcher.accepts(&file.as_string())).collect()
+        } else {
+            find_file_matches(&appended, &matcher)
+        };
+        if new_matches.is_empty() {
+            return Ok(());
+        }
+        info!("Filter found {} matches among appended lines", new_matches.len());
+        let mut new_ranked = self.rank(new_matches, &matcher).into_iter().peekable();
+        let mut ranks = Vec::with_capacity(self.ranks.len() + new_ranked.len());
+        let mut file_matches = Vec::with_capacity(ranks.capacity());
+        for (rank, file) in self.ranks.drain(..).zip(self.file_matches.drain(..)) {
+            let path = file.as_string();
+            while new_ranked.peek().map_or(false, |new| compare_ranked(&(new.0, new.1, &new.2), &(rank.0, rank.1, &path)) == Ordering::Less) {
+                let (typos, value, _, new_file) = new_ranked.next().unwrap();
+                ranks.push((typos, value));
+                file_matches.push(new_file);
+            }
+            ranks.push(rank);
+            file_matches.push(file);
+        }
+        for (typos, value, _, new_file) in new_ranked {
+            ranks.push((typos, value));
+            file_matches.push(new_file);
+        }
+        self.ranks = ranks;
+        self.file_matches = file_matches;
+        Ok(())
     }
 
-    pub fn re_filter(&mut self, new_regex: Regex) {
-        if self.new_regex_is_addative(&new_regex) {
+    pub fn re_filter(&mut self, new_query: Query) -> Result<()> {
+        if new_query.is_refinement_of(&self.query) {
             info!("Additive filter found, filtering only on previous matches");
-            self.file_matches = find_file_matches(&self.file_matches, new_regex.clone());
-            self.regex = new_regex.clone();
+            let matcher = try!(self.build_matcher(new_query.clone()));
+            self.file_matches = find_file_matches(&self.file_matches, &matcher);
+            self.query = new_query;
+            self.sort_matches(&matcher);
+            self.matcher = Some(matcher);
+            Ok(())
         } else {
             info!("Non addative filter found, filtering over all files");
-            self.regex = new_regex.clone();
-            self.run_filter();
+            self.query = new_query;
+            self.run_filter()
         }
     }
 
     //---------- private ---------//
 
-    fn new_regex_is_addative(&self, new_regex: &Regex) -> bool {
-        if self.regex.as_str() == "" {
-            return false;
-        }
-        new_regex.as_str().starts_with(self.regex.as_str())
+    fn build_matcher(&self, query: Query) -> Result<QueryMatcher> {
+        let matcher = try!(QueryMatcher::new(query)).with_predicates(self.predicates.clone());
+        Ok(match self.candidates {
+            CandidateSource::Directory(ref directory) => matcher.with_root(directory.path_string()),
+            CandidateSource::Lines(_) => matcher,
+        })
+    }
+
+    fn sort_matches(&mut self, matcher: &QueryMatcher) {
+        let files = self.file_matches.drain(..).collect();
+        let ranked = self.rank(files, matcher);
+        self.ranks = ranked.iter().map(|&(typos, value, _, _)| (typos, value)).collect();
+        self.file_matches = ranked.into_iter().map(|(_, _, _, file)| file).collect();
+    }
+
+    // the files sorted best first, with what they were sorted by
+    fn rank(&self, files: Vec<File>, matcher: &QueryMatcher) -> Vec<(usize, i64, String, File)> {
+        let match_all = self.query.is_match_all();
+        let mut ranked: Vec<(usize, i64, String, File)> = files.into_iter().map(|file| {
+            let path = file.as_string();
+            let (typos, value) = if match_all {
+                (0, 0)
+            } else {
+                matcher.score(&path).map_or((0, 0), |score| (score.typos, score.value))
+            };
+            (typos, value + self.ranking.boost(&self.query, &path), path, file)
+        }).collect();
+        ranked.sort_by(|a, b| compare_ranked(&(a.0, a.1, &a.2), &(b.0, b.1, &b.2)));
+        ranked
     }
 
     // TODO implement eq trait for this one
 }
 
+// exact matches before approximate ones, then best score (including any
+// ranking boosts) first, then shorter paths, then alphabetically
+fn compare_ranked(a: &(usize, i64, &String), b: &(usize, i64, &String)) -> Ordering {
+    a.0.cmp(&b.0)
+        .then(b.1.cmp(&a.1))
+        .then(a.2.len().cmp(&b.2.len()))
+        .then(a.2.cmp(b.2))
+}
+
 impl IntoIterator for FilteredDirectory {
     type Item = File;
     type IntoIter = FilteredDirectoryIntoIterator;
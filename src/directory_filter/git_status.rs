@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+
+use directory_scanner::Directory;
+use directory_filter::{Query, RankingSignal, FilePredicate, Result};
+use directory_filter::paths::normalize_path;
+
+const DIRTY_BOOST: i64 = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GitState {
+    Staged,
+    Modified,
+    Untracked,
+}
+
+impl GitState {
+
+    // as written on the command line and in requests
+    pub fn from_name(name: &str) -> Option<GitState> {
+        match name {
+            "staged" => Some(GitState::Staged),
+            "modified" => Some(GitState::Modified),
+            "untracked" => Some(GitState::Untracked),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FileStatus {
+    pub staged: bool,
+    pub modified: bool,
+    pub untracked: bool,
+}
+
+impl FileStatus {
+
+    pub fn is(&self, state: GitState) -> bool {
+        match state {
+            GitState::Staged => self.staged,
+            GitState::Modified => self.modified,
+            GitState::Untracked => self.untracked,
+        }
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.staged || self.modified || self.untracked
+    }
+}
+
+// A snapshot of the index and working tree of the repository containing a
+// root, read with the local `git` binary. Reading never writes to the
+// repository, git is told not to refresh the index or start an fsmonitor.
+pub struct GitStatus {
+    working_directory: PathBuf,
+    files: HashMap<PathBuf, FileStatus>,
+}
+
+impl GitStatus {
+
+    pub fn for_directory(directory: &Directory) -> Result<Self> {
+        GitStatus::read(Path::new(&directory.path_string()))
+    }
+
+    pub fn read(root: &Path) -> Result<Self> {
+        let working_directory = try!(env::current_dir());
+        let top_level = try!(git(root, &["rev-parse", "--show-toplevel"]));
+        let top_level = PathBuf::from(top_level.trim_end_matches('\n'));
+        let porcelain = try!(git(root, &["status", "--porcelain", "-z", "--untracked-files=all"]));
+
+        let mut files = HashMap::new();
+        // every entry is "XY path", except the bare path that follows a rename
+        // or copy, which can be any length
+        let mut entries = porcelain.split('\0');
+        while let Some(entry) = entries.next() {
+            if entry.is_empty() {
+                continue;
+            }
+            if entry.len() < 4 || entry.as_bytes()[2] != b' ' {
+                warn!("Skipping malformed git status entry {:?}", entry);
+                continue;
+            }
+            let mut codes = entry.chars();
+            let index = codes.next().unwrap_or(' ');
+            let working_tree = codes.next().unwrap_or(' ');
+            let status = FileStatus {
+                staged: index != ' ' && index != '?' && index != '!',
+                modified: working_tree != ' ' && working_tree != '?' && working_tree != '!',
+                untracked: index == '?',
+            };
+            files.insert(normalize_path(&top_level.join(&entry[3..])), status);
+            // the path a rename or copy came from isn't part of the status
+            if index == 'R' || index == 'C' {
+                entries.next();
+            }
+        }
+        Ok(GitStatus { working_directory: working_directory, files: files })
+    }
+
+    pub fn status(&self, path: &str) -> Option<&FileStatus> {
+        self.files.get(&normalize_path(&self.working_directory.join(path)))
+    }
+
+    pub fn is(&self, path: &str, state: GitState) -> bool {
+        self.status(path).map_or(false, |status| status.is(state))
+    }
+}
+
+impl RankingSignal for GitStatus {
+
+    fn boost(&self, _query: &Query, path: &str) -> i64 {
+        match self.status(path) {
+            Some(status) if status.is_dirty() => DIRTY_BOOST,
+            _ => 0,
+        }
+    }
+}
+
+pub struct GitStatusFilter {
+    git_status: Arc<GitStatus>,
+    state: GitState,
+}
+
+impl GitStatusFilter {
+
+    pub fn new(git_status: Arc<GitStatus>, state: GitState) -> Self {
+        GitStatusFilter { git_status: git_status, state: state }
+    }
+}
+
+impl FilePredicate for GitStatusFilter {
+
+    fn accepts(&self, path: &str) -> bool {
+        self.git_status.is(path, self.state)
+    }
+}
+
+//----------- private -------------//
+
+fn git(root: &Path, arguments: &[&str]) -> io::Result<String> {
+    let output = try!(Command::new("git")
+        .args(&["--no-optional-locks", "-c", "core.fsmonitor=false", "-C"])
+        .arg(root)
+        .args(arguments)
+        .output());
+    if !output.status.success() {
+        return Err(io::Error::new(io::ErrorKind::Other, String::from_utf8_lossy(&output.stderr).into_owned()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use directory_scanner::Directory;
+use directory_filter::{Query, RankingSignal, Result};
+
+const HALF_LIFE_SECONDS: f64 = 7.0 * 24.0 * 60.0 * 60.0;
+const SELECTION_BOOST: f64 = 40.0;
+const SAME_QUERY_MULTIPLIER: f64 = 2.0;
+const MAX_BOOST: f64 = 400.0;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Selection {
+    pub project: String,
+    pub query: String,
+    pub path: String,
+    pub selected_at: u64,
+}
+
+pub struct HistoryStore {
+    path: PathBuf,
+    selections: HashMap<String, HashMap<String, Vec<Selection>>>,
+}
+
+impl HistoryStore {
+
+    // $XDG_DATA_HOME/directory_filter/history, falling back to ~/.local/share
+    pub fn open_default() -> Result<Self> {
+        let data_dir = match env::var_os("XDG_DATA_HOME") {
+            Some(ref dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => {
+                let home = try!(env::var_os("HOME").ok_or(io::Error::new(io::ErrorKind::NotFound, "HOME is not set")));
+                PathBuf::from(home).join(".local").join("share")
+            }
+        };
+        HistoryStore::open(data_dir.join("directory_filter").join("history"))
+    }
+
+    pub fn open(path: PathBuf) -> Result<Self> {
+        let mut store = HistoryStore { path: path, selections: HashMap::new() };
+        if store.path.exists() {
+            let reader = BufReader::new(try!(File::open(&store.path)));
+            for line in reader.lines() {
+                match parse_line(&try!(line)) {
+                    Some(selection) => { store.insert(selection); },
+                    None => { warn!("Skipping malformed history line in {:?}", store.path); }
+                }
+            }
+        }
+        Ok(store)
+    }
+
+    pub fn record(&mut self, project: &str, query: &str, path: &str) -> Result<()> {
+        let selection = Selection {
+            project: project.to_string(),
+            query: query.to_string(),
+            path: path.to_string(),
+            selected_at: now(),
+        };
+        if let Some(parent) = self.path.parent() {
+            try!(fs::create_dir_all(parent));
+        }
+        let mut file = try!(OpenOptions::new().create(true).append(true).open(&self.path));
+        try!(writeln!(file, "{}", format_line(&selection)));
+        self.insert(selection);
+        Ok(())
+    }
+
+    pub fn selections(&self, project: &str, path: &str) -> &[Selection] {
+        match self.selections.get(project).and_then(|paths| paths.get(path)) {
+            Some(selections) => &selections[..],
+            None => &[],
+        }
+    }
+
+    //----------- private -------------//
+
+    fn insert(&mut self, selection: Selection) {
+        self.selections.entry(selection.project.clone())
+            .or_insert_with(HashMap::new)
+            .entry(selection.path.clone())
+            .or_insert_with(Vec::new)
+            .push(selection);
+    }
+}
+
+// Boosts paths that were selected often and recently within one project, with
+// each selection's weight halving every week. Selections made for the same
+// query count double.
+pub struct Frecency {
+    store: Arc<Mutex<HistoryStore>>,
+    project: String,
+}
+
+impl Frecency {
+
+    pub fn new(store: Arc<Mutex<HistoryStore>>, project: String) -> Self {
+        Frecency { store: store, project: project }
+    }
+
+    pub fn for_directory(store: Arc<Mutex<HistoryStore>>, directory: &Directory) -> Self {
+        Frecency::new(store, project_for(directory))
+    }
+}
+
+impl RankingSignal for Frecency {
+
+    fn boost(&self, query: &Query, path: &str) -> i64 {
+        let store = match self.store.lock() {
+            Ok(store) => store,
+            Err(_) => { return 0; }
+        };
+        let now = now();
+        let boost = store.selections(&self.project, path).iter().fold(0.0, |acc, selection| {
+            let age = now.saturating_sub(selection.selected_at) as f64;
+            let mut weight = SELECTION_BOOST * 0.5f64.powf(age / HALF_LIFE_SECONDS);
+            if selection.query == query.as_str() {
+                weight *= SAME_QUERY_MULTIPLIER;
+            }
+            acc + weight
+        });
+        boost.min(MAX_BOOST) as i64
+    }
+}
+
+pub fn project_for(directory: &Directory) -> String {
+    let root = directory.path_string();
+    match fs::canonicalize(&root) {
+        Ok(path) => path.to_string_lossy().into_owned(),
+        Err(_) => root,
+    }
+}
+
+//----------- private -------------//
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0)
+}
+
+fn format_line(selection: &Selection) -> String {
+    format!("{}\t{}\t{}\t{}", selection.selected_at, escape(&selection.project), escape(&selection.query), escape(&selection.path))
+}
+
+fn parse_line(line: &str) -> Option<Selection> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() != 4 {
+        return None;
+    }
+    fields[0].parse().ok().map(|selected_at| {
+        Selection {
+            project: unescape(fields[1]),
+            query: unescape(fields[2]),
+            path: unescape(fields[3]),
+            selected_at: selected_at,
+        }
+    })
+}
+
+fn escape(field: &str) -> String {
+    field.replace("\\", "\\\\").replace("\t", "\\t").replace("\n", "\\n")
+}
+
+fn unescape(field: &str) -> String {
+    let mut unescaped = String::new();
+    let mut characters = field.chars();
+    while let Some(character) = characters.next() {
+        if character == '\\' {
+            match characters.next() {
+                Some('t') => unescaped.push('\t'),
+                Some('n') => unescaped.push('\n'),
+                Some(other) => unescaped.push(other),
+                None => unescaped.push('\\'),
+            }
+        } else {
+            unescaped.push(character);
+        }
+    }
+    unescaped
+}
@@ -0,0 +1,171 @@
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Component, Path, PathBuf};
+
+use regex::Regex;
+
+use directory_filter::{RegexBuilder, MatchMode, FilePredicate, Result};
+use directory_filter::paths::absolute_path;
+
+const IGNORE_FILES: [&'static str; 2] = [".gitignore", ".ignore"];
+
+// Rejects paths with a component starting with a dot below the root.
+pub struct HiddenFilter {
+    root: PathBuf,
+}
+
+impl HiddenFilter {
+
+    pub fn new(root: &str) -> Self {
+        HiddenFilter { root: absolute_path(root) }
+    }
+}
+
+impl FilePredicate for HiddenFilter {
+
+    fn accepts(&self, path: &str) -> bool {
+        !relative_components(&self.root, path).iter().any(|component| component.starts_with('.'))
+    }
+}
+
+// A pattern from an ignore file, applying to the paths below the directory
+// the file is in.
+struct IgnoreRule {
+    base: Vec<String>,
+    regex: Regex,
+    negated: bool,
+    directory_only: bool,
+}
+
+// The .gitignore and .ignore patterns of a root and of the directories below
+// it. The directories are walked for ignore files once, skipping the ones
+// already ignored. Global excludes are not read.
+pub struct IgnoreRules {
+    root: PathBuf,
+    rules: Vec<IgnoreRule>,
+}
+
+impl IgnoreRules {
+
+    pub fn for_root(root: &str) -> Result<Self> {
+        let mut ignore_rules = IgnoreRules { root: absolute_path(root), rules: vec![] };
+        try!(ignore_rules.read_directory(Path::new(root), vec![]));
+        Ok(ignore_rules)
+    }
+
+    pub fn from_patterns(root: &str, patterns: &[String]) -> Result<Self> {
+        let mut ignore_rules = IgnoreRules { root: absolute_path(root), rules: vec![] };
+        try!(ignore_rules.add_patterns(&[], patterns));
+        Ok(ignore_rules)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn is_ignored(&self, path: &str) -> bool {
+        self.is_ignored_below_root(&relative_components(&self.root, path), false)
+    }
+
+    //----------- private -------------//
+
+    // ignore files nearer the path come later, so they override the ones above
+    fn read_directory(&mut self, directory: &Path, base: Vec<String>) -> Result<()> {
+        for ignore_file in IGNORE_FILES.iter() {
+            let path = directory.join(ignore_file);
+            if path.exists() {
+                let reader = BufReader::new(try!(File::open(&path)));
+                let mut patterns = vec![];
+                for line in reader.lines() {
+                    patterns.push(try!(line));
+                }
+                try!(self.add_patterns(&base, &patterns));
+            }
+        }
+        let entries = match fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(error) => {
+                warn!("Not reading ignore files below {:?}: {}", directory, error);
+                return Ok(());
+            }
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            // not following links keeps the walk inside the root
+            if !entry.file_type().map(|file_type| file_type.is_dir()).unwrap_or(false) || entry.file_name() == ".git" {
+                continue;
+            }
+            let mut components = base.clone();
+            components.push(entry.file_name().to_string_lossy().into_owned());
+            if !self.is_ignored_below_root(&components, true) {
+                try!(self.read_directory(&entry.path(), components));
+            }
+        }
+        Ok(())
+    }
+
+    fn add_patterns(&mut self, base: &[String], patterns: &[String]) -> Result<()> {
+        for pattern in patterns {
+            let pattern = pattern.trim_end();
+            if pattern.is_empty() || pattern.starts_with('#') {
+                continue;
+            }
+            let negated = pattern.starts_with('!');
+            let pattern = pattern.trim_start_matches('!');
+            let directory_only = pattern.ends_with('/');
+            let pattern = pattern.trim_end_matches('/');
+            // patterns with a slash are anchored to the ignore file's
+            // directory, others match a name at any depth; candidates are
+            // matched with a leading slash
+            let pattern = if pattern.contains('/') && !pattern.starts_with('/') {
+                format!("/{}", pattern)
+            } else {
+                pattern.to_string()
+            };
+            let regex = try!(RegexBuilder::new(pattern).case_sensitive(true).match_mode(MatchMode::Glob).build());
+            self.rules.push(IgnoreRule { base: base.to_vec(), regex: regex, negated: negated, directory_only: directory_only });
+        }
+        Ok(())
+    }
+
+    // later rules override earlier ones, and nothing below an ignored
+    // directory can be re-included
+    fn is_ignored_below_root(&self, components: &[String], is_directory: bool) -> bool {
+        for end in 1..components.len() + 1 {
+            let is_directory = is_directory || end < components.len();
+            let mut ignored = false;
+            for rule in &self.rules {
+                if (rule.directory_only && !is_directory) || end <= rule.base.len() || !components.starts_with(&rule.base) {
+                    continue;
+                }
+                let candidate = format!("/{}", components[rule.base.len()..end].join("/"));
+                if rule.regex.is_match(&candidate) {
+                    ignored = !rule.negated;
+                }
+            }
+            if ignored {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl FilePredicate for IgnoreRules {
+
+    fn accepts(&self, path: &str) -> bool {
+        !self.is_ignored(path)
+    }
+}
+
+//----------- private -------------//
+
+fn relative_components(root: &Path, path: &str) -> Vec<String> {
+    let path = absolute_path(path);
+    let relative = path.strip_prefix(root).unwrap_or(&path);
+    relative.components().filter_map(|component| {
+        match component {
+            Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+            _ => None,
+        }
+    }).collect()
+}
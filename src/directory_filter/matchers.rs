@@ -6,19 +6,150 @@ use std::thread;
 use crossbeam::sync::SegQueue;
 use scoped_threadpool::Pool;
 
-pub fn find_matches(directory: &Directory, regex: Regex) -> Vec<File> { // TODO this takes an event broker
-    execute(directory, regex)
+use directory_filter::{Query, MatchMode, FilePredicate, Result};
+use directory_filter::scorer::{self, Score};
+use directory_filter::normalizer::{normalize, is_normalizing};
+use directory_filter::paths::relative_path;
+
+#[derive(Clone)]
+pub struct QueryMatcher {
+    query: Query,
+    regex: Regex,
+    predicates: Vec<Arc<dyn FilePredicate>>,
+    root: Option<String>,
+}
+
+impl QueryMatcher {
+
+    pub fn new(query: Query) -> Result<Self> {
+        let normalized_query = normalize(query.as_str(), &query.options).string;
+        let query = Query::with_options(normalized_query, query.options.clone());
+        let regex = try!(query.regex());
+        Ok(QueryMatcher { query: query, regex: regex, predicates: vec![], root: None })
+    }
+
+    pub fn with_predicates(mut self, predicates: Vec<Arc<dyn FilePredicate>>) -> Self {
+        self.predicates = predicates;
+        self
+    }
+
+    // globs and anchored queries are matched against the path below the root,
+    // so `src/*.rs` and `/src` mean the same whichever path the root was
+    // scanned from
+    pub fn with_root(mut self, root: String) -> Self {
+        self.root = Some(root);
+        self
+    }
+
+    pub fn accepts(&self, path: &str) -> bool {
+        self.predicates.iter().all(|predicate| predicate.accepts(path))
+    }
+
+    pub fn is_match(&self, path: &str) -> bool {
+        if !self.accepts(path) {
+            return false;
+        }
+        let path = self.matched_path(path);
+        if is_normalizing(&self.query.options) {
+            self.is_normalized_match(&normalize(path, &self.query.options).string)
+        } else {
+            self.is_normalized_match(path)
+        }
+    }
+
+    // positions are always reported against the path as given, not its
+    // normalised or root relative form
+    pub fn score(&self, path: &str) -> Option<Score> {
+        let matched_path = self.matched_path(path);
+        let offset = path[..path.len() - matched_path.len()].chars().count();
+        let score = if is_normalizing(&self.query.options) {
+            let normalized_path = normalize(matched_path, &self.query.options);
+            self.normalized_score(&normalized_path.string).map(|score| {
+                Score {
+                    value: score.value,
+                    positions: normalized_path.original_positions(&score.positions),
+                    typos: score.typos,
+                }
+            })
+        } else {
+            self.normalized_score(matched_path)
+        };
+        score.map(|mut score| {
+            for position in score.positions.iter_mut() {
+                *position += offset;
+            }
+            score
+        })
+    }
+
+    // whether a match on a directory's path is a match on every file below it,
+    // which doesn't hold for anchored globs and regexes
+    pub fn matches_whole_directories(&self) -> bool {
+        match self.query.options.match_mode {
+            MatchMode::Glob | MatchMode::Regex => false,
+            MatchMode::Fuzzy | MatchMode::Initials | MatchMode::Exact => true,
+        }
+    }
+
+    //----------- private -------------//
+
+    fn matched_path<'a>(&self, path: &'a str) -> &'a str {
+        match self.root {
+            Some(ref root) if self.query.options.match_mode == MatchMode::Glob || self.query.is_anchored() => relative_path(root, path),
+            _ => path,
+        }
+    }
+
+    fn is_normalized_match(&self, path: &str) -> bool {
+        self.is_exact_match(path) || self.approximate_score(path).is_some()
+    }
+
+    fn is_exact_match(&self, path: &str) -> bool {
+        self.regex.is_match(path) && match self.query.options.match_mode {
+            MatchMode::Initials => scorer::score(&self.query, path).is_some(),
+            _ => true,
+        }
+    }
+
+    fn normalized_score(&self, path: &str) -> Option<Score> {
+        if !self.query.options.match_mode.is_fuzzy() {
+            self.regex.find(path).map(|found| scorer::span_score(path, found.start(), found.end()))
+        } else if self.is_exact_match(path) {
+            scorer::score(&self.query, path)
+        } else {
+            self.approximate_score(path)
+        }
+    }
+
+    // only ever asked once the exact match failed, so whatever it finds counts
+    // as at least one typo and ranks below every exact match
+    fn approximate_score(&self, path: &str) -> Option<Score> {
+        if !self.query.options.match_mode.is_fuzzy() {
+            return None;
+        }
+        match self.query.options.typo_budget {
+            0 => None,
+            budget => scorer::approximate_score(&self.query, path, budget).map(|mut score| {
+                score.typos = score.typos.max(1);
+                score
+            }),
+        }
+    }
+}
+
+pub fn find_matches(directory: &Directory, matcher: &QueryMatcher) -> Vec<File> { // TODO this takes an event broker
+    execute(directory, matcher)
 }
 
-pub fn find_file_matches(files: &Vec<File>, regex: Regex) -> Vec<File> {
+pub fn find_file_matches(files: &Vec<File>, matcher: &QueryMatcher) -> Vec<File> {
     let file_matches_queue = Arc::new(SegQueue::new());
     let mut pool = Pool::new(8); // TODO allow this to be variable
     pool.scoped(|scoped| {
         for file in files {
-        let local_regex = regex.clone();
+        let local_matcher = matcher.clone();
         let local_file_matches_queue = file_matches_queue.clone();
             scoped.execute(move || {
-                if is_string_match(file.as_string(), &local_regex) {
+                if local_matcher.is_match(&file.as_string()) {
                     local_file_matches_queue.push(file.clone());
                 }
             });
@@ -37,18 +168,23 @@ pub fn find_file_matches(files: &Vec<File>, regex: Regex) -> Vec<File> {
 
 //----------- private -------------//
 
-fn execute(directory: &Directory, regex: Regex) -> Vec<File> {
+fn execute(directory: &Directory, matcher: &QueryMatcher) -> Vec<File> {
     let file_matches_queue = Arc::new(SegQueue::new());
     let current_concurrency = Arc::new(AtomicUsize::new(0));
     let concurrency_limit = Arc::new(AtomicUsize::new(4));
-    fetch_matches(directory.clone(), regex.clone(), file_matches_queue.clone(), current_concurrency.clone(), concurrency_limit.clone());
+    fetch_matches(directory.clone(), matcher.clone(), file_matches_queue.clone(), current_concurrency.clone(), concurrency_limit.clone());
     let mut file_merged_matches = vec![];
     let mut done = false;
     while !done {
         match file_matches_queue.try_pop() {
             Some(matches) => { file_merged_matches.extend(matches); }
             None => {
-                if current_concurrency.load(Ordering::Relaxed) == 0 {
+                if current_concurrency.load(Ordering::SeqCst) == 0 {
+                    // a thread may have pushed its last matches between the
+                    // pop and the load
+                    while let Some(matches) = file_matches_queue.try_pop() {
+                        file_merged_matches.extend(matches);
+                    }
                     done = true
                 }
             }
@@ -58,27 +194,29 @@ fn execute(directory: &Directory, regex: Regex) -> Vec<File> {
 }
 
 
-fn fetch_matches(directory: Directory, regex: Regex, file_matches_queue: Arc<SegQueue<Vec<File>>>, current_concurrency: Arc<AtomicUsize>, concurrency_limit: Arc<AtomicUsize>) {
-    if is_string_match(directory.path_string(), &regex) {
-        file_matches_queue.push(directory.files());
+fn fetch_matches(directory: Directory, matcher: QueryMatcher, file_matches_queue: Arc<SegQueue<Vec<File>>>, current_concurrency: Arc<AtomicUsize>, concurrency_limit: Arc<AtomicUsize>) {
+    if matcher.matches_whole_directories() && matcher.is_match(&directory.path_string()) {
+        file_matches_queue.push(directory.files().into_iter().filter(|file| matcher.accepts(&file.as_string())).collect());
     } else {
         for file in directory.each_file() {
-            if is_string_match(file.as_string(), &regex) {
+            if matcher.is_match(&file.as_string()) {
                 file_matches_queue.push(vec![file.clone()]);
             }
         }
         for sub_directory in directory.each_sub_directory() {
             if max_concurrency_reached(current_concurrency.clone(), concurrency_limit.clone()) {
-                fetch_matches(sub_directory.clone(), regex.clone(), file_matches_queue.clone(), current_concurrency.clone(), concurrency_limit.clone());
+                fetch_matches(sub_directory.clone(), matcher.clone(), file_matches_queue.clone(), current_concurrency.clone(), concurrency_limit.clone());
             } else {
                 let local_current_concurrency = current_concurrency.clone();
                 let local_concurrency_limit = concurrency_limit.clone();
-                let local_regex = regex.clone();
+                let local_matcher = matcher.clone();
                 let local_file_matches_queue = file_matches_queue.clone();
+                // counted before the thread starts, so execute can't see zero
+                // running threads while this one has yet to begin
+                local_current_concurrency.fetch_add(1, Ordering::SeqCst);
                 thread::spawn(move || {
-                    local_current_concurrency.fetch_add(1, Ordering::SeqCst);
                     info!("Increased filter concurrency to {:?}", local_current_concurrency.load(Ordering::Relaxed));
-                    fetch_matches(sub_directory.clone(), local_regex, local_file_matches_queue, local_current_concurrency.clone(), local_concurrency_limit);
+                    fetch_matches(sub_directory.clone(), local_matcher, local_file_matches_queue, local_current_concurrency.clone(), local_concurrency_limit);
                     local_current_concurrency.fetch_sub(1, Ordering::SeqCst);
                     info!("Decreased filter concurrency to {:?}", local_current_concurrency.load(Ordering::Relaxed));
                 });
@@ -87,10 +225,6 @@ fn fetch_matches(directory: Directory, regex: Regex, file_matches_queue: Arc<Seg
     }
 }
 
-fn is_string_match(path: String, regex: &Regex) -> bool {
-    regex.is_match(&path)
-}
-
 fn max_concurrency_reached(current_concurrency: Arc<AtomicUsize>, concurrency_limit: Arc<AtomicUsize>) -> bool {
     current_concurrency.load(Ordering::Relaxed) >= concurrency_limit.load(Ordering::Relaxed)
 }
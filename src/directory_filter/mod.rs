@@ -1,14 +1,54 @@
+mod error;
 mod filtered_directory;
+mod candidates;
 //mod simple_filter;
 mod continuous_filter;
 mod matchers;
 mod regex_builder;
 mod filter_event_broker;
+mod filter_command;
+mod filter_handle;
+mod snapshot;
+mod subscribers;
+mod delta;
+mod multi_selection;
+#[cfg(feature = "async")]
+mod async_filter;
+mod query;
+mod scorer;
+mod normalizer;
+mod ranking;
+mod history;
+mod predicate;
+mod git_status;
+mod paths;
+mod proximity;
+mod ignore_rules;
+mod preview;
 
+pub use self::error::{Error, Result};
 pub use self::filtered_directory::FilteredDirectory;
+pub use self::candidates::{CandidateSource, CandidateList};
 //pub use self::simple_filter::SimpleFilter;
 pub use self::continuous_filter::ContinuousFilter;
 pub use self::regex_builder::RegexBuilder;
 pub use self::filter_event_broker::FilterEventBroker;
-pub use self::filter_event_broker::FILTER_EVENT_BROKER;
+pub use self::filter_handle::FilterHandle;
+pub use self::filter_command::{FilterCommand, Shared, Receipt};
+pub use self::snapshot::Snapshot;
+pub use self::subscribers::{Subscribers, SubscriptionId, LatestResult};
+pub use self::delta::{ResultDelta, DeltaOperation};
+pub use self::multi_selection::MultiSelection;
+#[cfg(feature = "async")]
+pub use self::async_filter::{QuerySink, ResultStream};
+pub use self::query::{Query, QueryOptions, MatchMode, CaseMode};
+pub use self::scorer::Score;
+pub use self::normalizer::Normalization;
+pub use self::ranking::{Ranking, RankingSignal};
+pub use self::history::{HistoryStore, Selection, Frecency, project_for};
+pub use self::predicate::FilePredicate;
+pub use self::git_status::{GitStatus, GitStatusFilter, GitState, FileStatus};
+pub use self::proximity::Proximity;
+pub use self::ignore_rules::{IgnoreRules, HiddenFilter};
+pub use self::preview::{Previewer, PreviewSource, Preview};
 
@@ -0,0 +1,125 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use directory_filter::FilteredDirectory;
+
+// The paths picked out of the results, in the order they were picked.
+//
+// Clones share the same selection. The continuous filter prunes it every time
+// results are published, so a selection survives query changes for as long as
+// the path still matches.
+#[derive(Clone)]
+pub struct MultiSelection {
+    state: Arc<Mutex<SelectionState>>,
+}
+
+struct SelectionState {
+    paths: Vec<String>,
+    lookup: HashSet<String>,
+}
+
+impl MultiSelection {
+
+    pub fn new() -> Self {
+        MultiSelection { state: Arc::new(Mutex::new(SelectionState { paths: vec![], lookup: HashSet::new() })) }
+    }
+
+    // returns whether the path is selected afterwards
+    pub fn toggle(&self, path: &str) -> bool {
+        let mut state = self.lock_state();
+        if state.lookup.contains(path) {
+            state.remove(path);
+            false
+        } else {
+            state.insert(path);
+            true
+        }
+    }
+
+    pub fn select(&self, path: &str) {
+        self.lock_state().insert(path);
+    }
+
+    pub fn deselect(&self, path: &str) {
+        self.lock_state().remove(path);
+    }
+
+    pub fn select_all(&self, filtered_directory: &FilteredDirectory) {
+        let mut state = self.lock_state();
+        for file in &filtered_directory.file_matches {
+            state.insert(&file.as_string());
+        }
+    }
+
+    // flips every current match, anything selected that no longer matches is
+    // left alone
+    pub fn invert(&self, filtered_directory: &FilteredDirectory) {
+        let mut state = self.lock_state();
+        for file in &filtered_directory.file_matches {
+            let path = file.as_string();
+            if state.lookup.contains(&path) {
+                state.remove(&path);
+            } else {
+                state.insert(&path);
+            }
+        }
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.lock_state();
+        state.paths.clear();
+        state.lookup.clear();
+    }
+
+    pub fn retain_matching(&self, filtered_directory: &FilteredDirectory) {
+        let mut state = self.lock_state();
+        if state.paths.is_empty() {
+            return;
+        }
+        let matching: HashSet<String> = filtered_directory.file_matches.iter().map(|file| file.as_string()).collect();
+        state.paths.retain(|path| matching.contains(path));
+        state.lookup.retain(|path| matching.contains(path));
+    }
+
+    pub fn is_selected(&self, path: &str) -> bool {
+        self.lock_state().lookup.contains(path)
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock_state().paths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn paths(&self) -> Vec<String> {
+        self.lock_state().paths.clone()
+    }
+
+    //----------- private -------------//
+
+    // every update leaves the state consistent, so it is still usable after a
+    // panic while holding the lock
+    fn lock_state(&self) -> MutexGuard<SelectionState> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl SelectionState {
+
+    fn insert(&mut self, path: &str) {
+        if self.lookup.insert(path.to_string()) {
+            self.paths.push(path.to_string());
+        }
+    }
+
+    fn remove(&mut self, path: &str) {
+        if self.lookup.remove(path) {
+            self.paths.retain(|selected| selected != path);
+        }
+    }
+}
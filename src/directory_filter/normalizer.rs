@@ -0,0 +1,74 @@
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+
+use directory_filter::QueryOptions;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Normalization {
+    None,
+    Nfc,
+    Nfkc,
+}
+
+impl Default for Normalization {
+    fn default() -> Self {
+        Normalization::None
+    }
+}
+
+pub struct NormalizedString {
+    pub string: String,
+    origins: Vec<usize>,
+}
+
+impl NormalizedString {
+
+    // maps char indices in the normalised string back to char indices in the
+    // string it was normalised from
+    pub fn original_positions(&self, positions: &[usize]) -> Vec<usize> {
+        let mut original_positions: Vec<usize> = positions.iter().map(|position| self.origins[*position]).collect();
+        original_positions.dedup();
+        original_positions
+    }
+}
+
+pub fn is_normalizing(options: &QueryOptions) -> bool {
+    options.normalization != Normalization::None || options.fold_diacritics
+}
+
+// Normalises one cluster (a character and the combining marks that follow it)
+// at a time so that every output character can be traced back to where its
+// cluster started in the original string.
+pub fn normalize(string: &str, options: &QueryOptions) -> NormalizedString {
+    let mut normalized = NormalizedString { string: String::new(), origins: vec![] };
+    let mut cluster = String::new();
+    let mut cluster_start = 0;
+    for (index, character) in string.chars().enumerate() {
+        if !cluster.is_empty() && !is_combining_mark(character) {
+            push_cluster(&mut normalized, &cluster, cluster_start, options);
+            cluster.clear();
+            cluster_start = index;
+        }
+        cluster.push(character);
+    }
+    if !cluster.is_empty() {
+        push_cluster(&mut normalized, &cluster, cluster_start, options);
+    }
+    normalized
+}
+
+//----------- private -------------//
+
+fn push_cluster(normalized: &mut NormalizedString, cluster: &str, cluster_start: usize, options: &QueryOptions) {
+    let characters: Vec<char> = match (options.normalization, options.fold_diacritics) {
+        (Normalization::Nfkc, true) => cluster.nfkd().filter(|character| !is_combining_mark(*character)).collect(),
+        (_, true) => cluster.nfd().filter(|character| !is_combining_mark(*character)).collect(),
+        (Normalization::Nfkc, false) => cluster.nfkc().collect(),
+        (Normalization::Nfc, false) => cluster.nfc().collect(),
+        (Normalization::None, false) => cluster.chars().collect(),
+    };
+    for character in characters {
+        normalized.string.push(character);
+        normalized.origins.push(cluster_start);
+    }
+}
@@ -0,0 +1,43 @@
+use std::env;
+use std::path::{Component, Path, PathBuf};
+
+pub fn absolute_path(path: &str) -> PathBuf {
+    match env::current_dir() {
+        Ok(working_directory) => absolute_path_from(&working_directory, path),
+        Err(_) => normalize_path(Path::new(path)),
+    }
+}
+
+// for callers resolving many paths against a working directory looked up once
+pub fn absolute_path_from(working_directory: &Path, path: &str) -> PathBuf {
+    let path = Path::new(path);
+    if path.is_absolute() {
+        normalize_path(path)
+    } else {
+        normalize_path(&working_directory.join(path))
+    }
+}
+
+// the part of the path below the root, paths outside of it are returned as
+// they are
+pub fn relative_path<'a>(root: &str, path: &'a str) -> &'a str {
+    let root = root.trim_end_matches('/');
+    if path.starts_with(root) && path[root.len()..].starts_with('/') {
+        path[root.len()..].trim_start_matches('/')
+    } else {
+        path
+    }
+}
+
+// resolves `.` and `..` without touching the file system
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => { normalized.pop(); },
+            other => normalized.push(other.as_os_str()),
+        }
+    }
+    normalized
+}
@@ -0,0 +1,3 @@
+pub trait FilePredicate: Send + Sync {
+    fn accepts(&self, path: &str) -> bool;
+}
@@ -0,0 +1,221 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender, Receiver};
+use std::thread;
+
+const BINARY_NOTICE: &'static str = "(binary file)";
+const SPECIAL_FILE_NOTICE: &'static str = "(not a regular file)";
+// however long the lines are, a preview never reads more than this
+const MAX_PREVIEW_BYTES: u64 = 256 * 1024;
+const MAX_LINE_BYTES: u64 = 4096;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PreviewSource {
+    Contents,
+    // run through `sh -c` with every `{}` replaced by the quoted path
+    Command(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Preview {
+    pub path: String,
+    pub lines: Vec<String>,
+}
+
+enum PreviewEvent {
+    Request(usize, String),
+    Cancel,
+    Read(usize, String, io::Result<Option<Vec<String>>>),
+}
+
+// Reads previews in the background. Only the latest request matters, a read
+// still running for an earlier one is abandoned and its command killed as soon
+// as a new request comes in. Queries only ever match paths, so previews start
+// at the top of the file.
+pub struct Previewer {
+    events: Sender<PreviewEvent>,
+    latest_request: Arc<AtomicUsize>,
+}
+
+impl Previewer {
+
+    pub fn new<F>(source: PreviewSource, max_lines: usize, callback: F) -> Self where F: Fn(Preview) + Send + 'static {
+        let (events, receiver) = channel();
+        let latest_request = Arc::new(AtomicUsize::new(0));
+        let worker = Worker { source: source, max_lines: max_lines, events: events.clone(), latest_request: latest_request.clone(), command: None };
+        thread::spawn(move || {
+            worker.run(receiver, callback);
+        });
+        Previewer { events: events, latest_request: latest_request }
+    }
+
+    pub fn request(&self, path: &str) {
+        let id = self.latest_request.fetch_add(1, Ordering::SeqCst) + 1;
+        let _ = self.events.send(PreviewEvent::Request(id, path.to_string()));
+    }
+
+    // abandons whatever is being read, e.g. when nothing is highlighted
+    pub fn cancel(&self) {
+        self.latest_request.fetch_add(1, Ordering::SeqCst);
+        let _ = self.events.send(PreviewEvent::Cancel);
+    }
+}
+
+//----------- private -------------//
+
+// Starts every read on a thread of its own, so one that blocks never holds up
+// the next request.
+struct Worker {
+    source: PreviewSource,
+    max_lines: usize,
+    events: Sender<PreviewEvent>,
+    latest_request: Arc<AtomicUsize>,
+    command: Option<Child>,
+}
+
+impl Worker {
+
+    fn run<F>(mut self, receiver: Receiver<PreviewEvent>, callback: F) where F: Fn(Preview) {
+        while let Ok(event) = receiver.recv() {
+            match event {
+                PreviewEvent::Request(id, path) => {
+                    self.stop_command();
+                    if self.is_latest(id) {
+                        if let Err(error) = self.start(id, path.clone()) {
+                            callback(failed_preview(path, error));
+                        }
+                    }
+                },
+                PreviewEvent::Cancel => { self.stop_command(); },
+                PreviewEvent::Read(id, path, lines) => {
+                    if !self.is_latest(id) {
+                        continue;
+                    }
+                    // the command may still be producing output we don't need
+                    self.stop_command();
+                    match lines {
+                        Ok(Some(lines)) => { callback(Preview { path: path, lines: lines }); },
+                        Ok(None) => {},
+                        Err(error) => { callback(failed_preview(path, error)); }
+                    }
+                }
+            }
+        }
+        self.stop_command();
+    }
+
+    fn is_latest(&self, id: usize) -> bool {
+        self.latest_request.load(Ordering::SeqCst) == id
+    }
+
+    fn start(&mut self, id: usize, path: String) -> io::Result<()> {
+        let max_lines = self.max_lines;
+        let events = self.events.clone();
+        let latest_request = self.latest_request.clone();
+        let is_cancelled = move || latest_request.load(Ordering::SeqCst) != id;
+        match self.source {
+            PreviewSource::Contents => {
+                thread::spawn(move || {
+                    let lines = read_contents(&path, max_lines, &is_cancelled);
+                    let _ = events.send(PreviewEvent::Read(id, path, lines));
+                });
+            },
+            PreviewSource::Command(ref command) => {
+                let command = command.replace("{}", &shell_quote(&path));
+                let mut child = try!(Command::new("sh").arg("-c").arg(&command)
+                    .stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::null())
+                    .spawn());
+                let stdout = child.stdout.take();
+                self.command = Some(child);
+                // killing the command closes its output, which ends the read
+                thread::spawn(move || {
+                    let lines = match stdout {
+                        Some(stdout) => read_lines(stdout, max_lines, &is_cancelled),
+                        None => Ok(Some(vec![])),
+                    };
+                    let _ = events.send(PreviewEvent::Read(id, path, lines));
+                });
+            },
+        }
+        Ok(())
+    }
+
+    fn stop_command(&mut self) {
+        if let Some(mut child) = self.command.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+fn failed_preview(path: String, error: io::Error) -> Preview {
+    Preview { path: path, lines: vec![format!("(preview failed: {})", error)] }
+}
+
+// None when the read was cancelled. Opening a fifo or a device could block
+// for good, so only regular files are read.
+fn read_contents(path: &str, max_lines: usize, is_cancelled: &dyn Fn() -> bool) -> io::Result<Option<Vec<String>>> {
+    if !try!(fs::metadata(path)).is_file() {
+        return Ok(Some(vec![SPECIAL_FILE_NOTICE.to_string()]));
+    }
+    read_lines(try!(File::open(path)), max_lines, is_cancelled)
+}
+
+// lines longer than MAX_LINE_BYTES are cut short and the rest of them skipped
+fn read_lines<R: Read>(reader: R, max_lines: usize, is_cancelled: &dyn Fn() -> bool) -> io::Result<Option<Vec<String>>> {
+    let mut reader = BufReader::new(reader.take(MAX_PREVIEW_BYTES));
+    let mut lines = vec![];
+    let mut buffer = vec![];
+    while lines.len() < max_lines {
+        if is_cancelled() {
+            return Ok(None);
+        }
+        buffer.clear();
+        if try!((&mut reader).take(MAX_LINE_BYTES).read_until(b'\n', &mut buffer)) == 0 {
+            break;
+        }
+        if buffer.contains(&0) {
+            return Ok(Some(vec![BINARY_NOTICE.to_string()]));
+        }
+        if !buffer.ends_with(b"\n") && !try!(skip_line(&mut reader, is_cancelled)) {
+            return Ok(None);
+        }
+        let line = String::from_utf8_lossy(&buffer);
+        lines.push(printable(line.trim_end_matches(|character| character == '\n' || character == '\r')));
+    }
+    Ok(Some(lines))
+}
+
+// false when cancelled before reaching the end of the line
+fn skip_line<R: BufRead>(reader: &mut R, is_cancelled: &dyn Fn() -> bool) -> io::Result<bool> {
+    let mut rest = vec![];
+    loop {
+        if is_cancelled() {
+            return Ok(false);
+        }
+        rest.clear();
+        if try!(reader.take(MAX_LINE_BYTES).read_until(b'\n', &mut rest)) == 0 || rest.ends_with(b"\n") {
+            return Ok(true);
+        }
+    }
+}
+
+// tabs become spaces and other control characters are dropped so they can't
+// move the terminal cursor around
+fn printable(line: &str) -> String {
+    line.chars().fold(String::new(), |mut acc, character| {
+        if character == '\t' {
+            acc.push_str("    ");
+        } else if !character.is_control() {
+            acc.push(character);
+        }
+        acc
+    })
+}
+
+fn shell_quote(path: &str) -> String {
+    format!("'{}'", path.replace('\'', "'\\''"))
+}
@@ -0,0 +1,104 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use directory_filter::{Query, RankingSignal};
+use directory_filter::paths::absolute_path_from;
+
+const SHARED_DIRECTORY_BOOST: i64 = 4;
+const SIBLING_BOOST: i64 = 16;
+const COUNTERPART_BOOST: i64 = 48;
+
+const TEST_DIRECTORIES: [&'static str; 5] = ["test", "tests", "spec", "specs", "__tests__"];
+const TEST_SUFFIXES: [&'static str; 6] = ["_test", "_spec", "-test", "-spec", ".test", ".spec"];
+const TEST_PREFIXES: [&'static str; 2] = ["test_", "spec_"];
+
+// Boosts paths close to the file currently open in an editor: the more parent
+// directories they share the better, with siblings and the test or
+// implementation counterpart of the current file ranked highest.
+pub struct Proximity {
+    working_directory: PathBuf,
+    current_path: PathBuf,
+}
+
+impl Proximity {
+
+    pub fn new(current_path: &str) -> Self {
+        let working_directory = env::current_dir().unwrap_or(PathBuf::new());
+        let current_path = absolute_path_from(&working_directory, current_path);
+        Proximity { working_directory: working_directory, current_path: current_path }
+    }
+}
+
+impl RankingSignal for Proximity {
+
+    fn boost(&self, _query: &Query, path: &str) -> i64 {
+        let path = absolute_path_from(&self.working_directory, path);
+        if path == self.current_path {
+            return 0;
+        }
+        let current_parent = self.current_path.parent();
+        let parent = path.parent();
+        let shared = shared_directories(&self.current_path, &path);
+
+        let mut boost = shared as i64 * SHARED_DIRECTORY_BOOST;
+        if parent.is_some() && parent == current_parent {
+            boost += SIBLING_BOOST;
+        }
+        if is_counterpart(&self.current_path, &path) {
+            boost += COUNTERPART_BOOST;
+        }
+        boost
+    }
+}
+
+//----------- private -------------//
+
+// `foo.rs` and `foo_test.rs`, `test_foo.py` or `tests/foo.rs`. Only the
+// directories below the ones both paths share can make a path a test, so
+// everything under a checkout in ~/tests isn't.
+fn is_counterpart(a: &Path, b: &Path) -> bool {
+    let shared = shared_directories(a, b);
+    if is_test(a, shared) == is_test(b, shared) || a.extension() != b.extension() {
+        return false;
+    }
+    match (file_stem(a), file_stem(b)) {
+        (Some(a), Some(b)) => subject(&a) == subject(&b),
+        _ => false,
+    }
+}
+
+// how many leading directories the two paths have in common
+fn shared_directories(a: &Path, b: &Path) -> usize {
+    match (a.parent(), b.parent()) {
+        (Some(a), Some(b)) => a.components().zip(b.components()).take_while(|&(a, b)| a == b).count(),
+        _ => 0,
+    }
+}
+
+fn is_test(path: &Path, shared: usize) -> bool {
+    let in_test_directory = path.parent().map_or(false, |parent| {
+        parent.components().skip(shared).any(|component| {
+            let name = component.as_os_str().to_string_lossy();
+            TEST_DIRECTORIES.iter().any(|directory| *directory == name)
+        })
+    });
+    in_test_directory || file_stem(path).map_or(false, |stem| subject(&stem) != stem)
+}
+
+fn file_stem(path: &Path) -> Option<String> {
+    path.file_stem().map(|stem| stem.to_string_lossy().into_owned())
+}
+
+fn subject(stem: &str) -> String {
+    for suffix in TEST_SUFFIXES.iter() {
+        if stem.ends_with(suffix) && stem.len() > suffix.len() {
+            return stem[..stem.len() - suffix.len()].to_string();
+        }
+    }
+    for prefix in TEST_PREFIXES.iter() {
+        if stem.starts_with(prefix) && stem.len() > prefix.len() {
+            return stem[prefix.len()..].to_string();
+        }
+    }
+    stem.to_string()
+}
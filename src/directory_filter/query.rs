@@ -0,0 +1,130 @@
+use regex::Regex;
+
+use directory_filter::{RegexBuilder, Normalization, Result};
+
+// Fuzzy and Initials are scored subsequence matches. Glob, Regex and Exact
+// match literally, without typo tolerance, and are ranked by where the match
+// falls in the path.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MatchMode {
+    Fuzzy,
+    Initials,
+    Glob,
+    Regex,
+    Exact,
+}
+
+impl MatchMode {
+
+    pub fn is_fuzzy(&self) -> bool {
+        match *self {
+            MatchMode::Fuzzy | MatchMode::Initials => true,
+            MatchMode::Glob | MatchMode::Regex | MatchMode::Exact => false,
+        }
+    }
+}
+
+impl Default for MatchMode {
+    fn default() -> Self {
+        MatchMode::Fuzzy
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CaseMode {
+    Smart,
+    Ignore,
+    Respect,
+}
+
+impl CaseMode {
+
+    // smart case only respects case once the query contains an uppercase
+    // character, in any script
+    pub fn is_case_sensitive(&self, string: &str) -> bool {
+        match *self {
+            CaseMode::Smart => string.chars().any(|character| character.is_uppercase()),
+            CaseMode::Ignore => false,
+            CaseMode::Respect => true,
+        }
+    }
+}
+
+impl Default for CaseMode {
+    fn default() -> Self {
+        CaseMode::Smart
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct QueryOptions {
+    pub match_mode: MatchMode,
+    pub case_mode: CaseMode,
+    pub normalization: Normalization,
+    pub fold_diacritics: bool,
+    pub typo_budget: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Query {
+    string: String,
+    pub options: QueryOptions,
+}
+
+impl Query {
+
+    pub fn new(string: String) -> Self {
+        Query::with_options(string, QueryOptions::default())
+    }
+
+    pub fn with_options(string: String, options: QueryOptions) -> Self {
+        Query { string: string, options: options }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.string
+    }
+
+    pub fn is_match_all(&self) -> bool {
+        self.string == "" || self.string == ".*"
+    }
+
+    pub fn is_case_sensitive(&self) -> bool {
+        self.options.case_mode.is_case_sensitive(&self.string)
+    }
+
+    // a leading `/` ties the query to the top of the scanned root, see
+    // RegexBuilder
+    pub fn is_anchored(&self) -> bool {
+        self.options.match_mode.is_fuzzy() && self.string.starts_with('/')
+    }
+
+    pub fn regex(&self) -> Result<Regex> {
+        RegexBuilder::new(self.string.clone())
+            .case_sensitive(self.is_case_sensitive())
+            .match_mode(self.options.match_mode)
+            .build()
+    }
+
+    // a refinement can only ever match a subset of what the previous query
+    // matched, so it is safe to filter over the previous matches only. Typing
+    // on to a glob or regex can match more, e.g. `a` then `a|b`.
+    pub fn is_refinement_of(&self, previous: &Query) -> bool {
+        if previous.string == "" || !self.string.starts_with(&previous.string) {
+            return false;
+        }
+        if self.options.match_mode == MatchMode::Glob || self.options.match_mode == MatchMode::Regex {
+            return false;
+        }
+        if previous.is_case_sensitive() && !self.is_case_sensitive() {
+            return false;
+        }
+        if self.options.typo_budget > previous.options.typo_budget {
+            return false;
+        }
+        let mut options = self.options.clone();
+        options.case_mode = previous.options.case_mode;
+        options.typo_budget = previous.options.typo_budget;
+        options == previous.options
+    }
+}
@@ -0,0 +1,31 @@
+use std::sync::Arc;
+
+use directory_filter::Query;
+
+pub trait RankingSignal: Send + Sync {
+    fn boost(&self, query: &Query, path: &str) -> i64;
+}
+
+#[derive(Clone, Default)]
+pub struct Ranking {
+    signals: Vec<Arc<dyn RankingSignal>>,
+}
+
+impl Ranking {
+
+    pub fn new() -> Self {
+        Ranking { signals: vec![] }
+    }
+
+    pub fn add_signal(&mut self, signal: Arc<dyn RankingSignal>) {
+        self.signals.push(signal);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.signals.is_empty()
+    }
+
+    pub fn boost(&self, query: &Query, path: &str) -> i64 {
+        self.signals.iter().fold(0, |acc, signal| acc + signal.boost(query, path))
+    }
+}
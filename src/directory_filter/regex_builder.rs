@@ -1,34 +1,136 @@
-use regex::Regex;
+use regex::{self, Regex};
+
+use directory_filter::{CaseMode, MatchMode, Result};
 
 pub struct RegexBuilder {
     string: String,
+    case_sensitive: bool,
+    match_mode: MatchMode,
 }
 
 impl RegexBuilder {
 
     pub fn new(string: String) -> Self {
-        RegexBuilder { string: string }
+        let case_sensitive = CaseMode::Smart.is_case_sensitive(&string);
+        RegexBuilder { string: string, case_sensitive: case_sensitive, match_mode: MatchMode::Fuzzy }
+    }
+
+    pub fn case_sensitive(mut self, case_sensitive: bool) -> Self {
+        self.case_sensitive = case_sensitive;
+        self
+    }
+
+    pub fn match_mode(mut self, match_mode: MatchMode) -> Self {
+        self.match_mode = match_mode;
+        self
     }
 
-    pub fn build(&self) -> Regex {
+    pub fn build(&self) -> Result<Regex> {
+        let new_string = match self.match_mode {
+            MatchMode::Regex => format!("{}{}", self.global_flag(), self.string),
+            MatchMode::Exact => format!("{}{}", self.global_flag(), regex::escape(&self.string)),
+            MatchMode::Glob => self.glob_pattern(),
+            MatchMode::Fuzzy | MatchMode::Initials => {
+                if self.string.contains('/') {
+                    self.segmented_pattern()
+                } else {
+                    self.fuzzy_pattern()
+                }
+            }
+        };
+        Regex::new(&new_string).map_err(From::from)
+    }
+
+    //----------- private -----------//
+
+    fn fuzzy_pattern(&self) -> String {
         let mut new_string = self.string.chars().fold(String::new(), |mut acc, character|{
             acc.push_str(self.global_flag());
             acc.push_str(".*");
-            acc.push(character);
+            acc.push_str(&regex::escape(&character.to_string()));
             acc
         });
         new_string.push_str(".*");
-        Regex::new(&new_string).unwrap()
+        new_string
     }
 
-    //----------- private -----------//
+    // each query segment has to match within a single path component, and the
+    // segments have to match components in the same order they were typed. A
+    // leading `/` anchors the first segment to the first component, which the
+    // matcher takes to be the first one below the scanned root.
+    fn segmented_pattern(&self) -> String {
+        let segments: Vec<String> = self.string.split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| self.segment_pattern(segment))
+            .collect();
+        let mut new_string = String::from(self.global_flag());
+        if self.string.starts_with('/') {
+            new_string.push('^');
+        }
+        new_string.push_str(&segments.join("[^/]*/(?:.*/)?"));
+        if self.string.ends_with('/') {
+            new_string.push_str("[^/]*/");
+        }
+        new_string
+    }
+
+    fn segment_pattern(&self, segment: &str) -> String {
+        segment.chars().fold(String::new(), |mut acc, character|{
+            acc.push_str("[^/]*");
+            acc.push_str(&regex::escape(&character.to_string()));
+            acc
+        })
+    }
+
+    // `*` and `?` stay within a path component and `**` crosses them. Globs
+    // without a `/` only have to match the file name, like in a .gitignore.
+    fn glob_pattern(&self) -> String {
+        let mut new_string = String::from(self.global_flag());
+        new_string.push_str(if self.string.contains('/') { "^" } else { "(?:^|/)" });
+        let mut characters = self.string.chars().peekable();
+        let mut in_class = false;
+        while let Some(character) = characters.next() {
+            match character {
+                ']' if in_class => {
+                    in_class = false;
+                    new_string.push(']');
+                },
+                _ if in_class => {
+                    if character == '\\' || character == '[' {
+                        new_string.push('\\');
+                    }
+                    new_string.push(character);
+                },
+                '*' => {
+                    if characters.peek() == Some(&'*') {
+                        characters.next();
+                        new_string.push_str(".*");
+                    } else {
+                        new_string.push_str("[^/]*");
+                    }
+                },
+                '?' => { new_string.push_str("[^/]"); },
+                '[' => {
+                    in_class = true;
+                    new_string.push('[');
+                    if characters.peek() == Some(&'!') {
+                        characters.next();
+                        new_string.push('^');
+                    }
+                },
+                _ => { new_string.push_str(&regex::escape(&character.to_string())); }
+            }
+        }
+        new_string.push('$');
+        new_string
+    }
 
     fn global_flag(&self) -> &'static str {
-        let mut prefix = "(?i)";
-        if Regex::new("[A-Z]+").unwrap().is_match(&self.string) {
-            prefix = "";
+        if self.case_sensitive {
+            ""
+        } else {
+            "(?i)"
         }
-        prefix
     }
 
 }
@@ -0,0 +1,285 @@
+use directory_filter::{Query, MatchMode};
+
+const SCORE_MATCH: i64 = 16;
+const SCORE_GAP: i64 = 1;
+const BONUS_BOUNDARY: i64 = 24;
+const BONUS_CONSECUTIVE: i64 = 12;
+const BONUS_BASENAME: i64 = 8;
+const PENALTY_TYPO: i64 = 32;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Score {
+    pub value: i64,
+    pub positions: Vec<usize>,
+    pub typos: usize,
+}
+
+// Finds the best alignment of the query characters within the path, favouring
+// word boundaries (path separators, `_`, `-`, `.` and camelCase humps),
+// consecutive runs and the file name over its parent directories.
+//
+// Positions are char indices into the path. The first character of an
+// anchored query has to fall within the first path component, the leading
+// `/` isn't matched itself.
+pub fn score(query: &Query, path: &str) -> Option<Score> {
+    let anchored = query.is_anchored();
+    let needle: Vec<char> = query.as_str().trim_start_matches('/').chars().collect();
+    let haystack: Vec<char> = path.chars().collect();
+    if needle.is_empty() {
+        return Some(Score { value: 0, positions: vec![], typos: 0 });
+    }
+    if needle.len() > haystack.len() {
+        return None;
+    }
+
+    let case_sensitive = query.is_case_sensitive();
+    let initials_only = query.options.match_mode == MatchMode::Initials;
+    let basename_start = haystack.iter().rposition(|character| *character == '/').map_or(0, |index| index + 1);
+    let first_component_end = haystack.iter().position(|character| *character == '/').unwrap_or(haystack.len());
+    let width = haystack.len();
+    let mut scores: Vec<Option<i64>> = vec![None; needle.len() * width];
+    let mut previous: Vec<usize> = vec![0; needle.len() * width];
+
+    for (row, query_character) in needle.iter().enumerate() {
+        // best score of the previous row strictly left of the current column,
+        // with the gap penalty for the skipped characters already applied
+        let mut best_before: Option<(i64, usize)> = None;
+        for column in 0..width {
+            let mut consecutive = None;
+            if row > 0 && column > 0 {
+                best_before = best_before.map(|(value, index)| (value - SCORE_GAP, index));
+                if let Some(value) = scores[(row - 1) * width + column - 1] {
+                    if best_before.map_or(true, |(best, _)| value > best) {
+                        best_before = Some((value, column - 1));
+                    }
+                    consecutive = Some(value + BONUS_CONSECUTIVE);
+                }
+            }
+            if !characters_match(*query_character, haystack[column], case_sensitive) {
+                continue;
+            }
+            if anchored && row == 0 && column >= first_component_end {
+                continue;
+            }
+            // a `/` separates segments rather than standing for an initial
+            let boundary = is_boundary(&haystack, column);
+            if initials_only && !boundary && *query_character != '/' {
+                continue;
+            }
+
+            let mut bonus = SCORE_MATCH;
+            if boundary {
+                bonus += BONUS_BOUNDARY;
+            }
+            if column >= basename_start {
+                bonus += BONUS_BASENAME;
+            }
+
+            let cell = if row == 0 {
+                Some((bonus, column))
+            } else {
+                match (best_before, consecutive) {
+                    (Some((value, index)), Some(consecutive_value)) => {
+                        if consecutive_value >= value {
+                            Some((consecutive_value + bonus, column - 1))
+                        } else {
+                            Some((value + bonus, index))
+                        }
+                    },
+                    (Some((value, index)), None) => Some((value + bonus, index)),
+                    (None, _) => None,
+                }
+            };
+            if let Some((value, index)) = cell {
+                scores[row * width + column] = Some(value);
+                previous[row * width + column] = index;
+            }
+        }
+    }
+
+    let last_row = needle.len() - 1;
+    let mut best: Option<(i64, usize)> = None;
+    for column in 0..width {
+        if let Some(value) = scores[last_row * width + column] {
+            if best.map_or(true, |(best_value, _)| value > best_value) {
+                best = Some((value, column));
+            }
+        }
+    }
+
+    best.map(|(value, column)| {
+        let mut positions = vec![column];
+        let mut current = column;
+        for row in (1..needle.len()).rev() {
+            current = previous[row * width + current];
+            positions.push(current);
+        }
+        positions.reverse();
+        Score { value: value, positions: positions, typos: 0 }
+    })
+}
+
+// Matches the query allowing up to `budget` dropped, substituted or
+// transposed query characters. Skipping path characters is free, as it is for
+// the exact fuzzy match. The same rules apply as for exact matches: a space is
+// matched like any other character, initials only match on word boundaries
+// and queries with a `/` are aligned segment by segment.
+pub fn approximate_score(query: &Query, path: &str, budget: usize) -> Option<Score> {
+    let haystack: Vec<char> = path.chars().collect();
+    let case_sensitive = query.is_case_sensitive();
+    let initials_only = query.options.match_mode == MatchMode::Initials;
+    let (typos, positions) = if query.as_str().contains('/') {
+        match segmented_alignment(query.as_str(), &haystack, case_sensitive, initials_only) {
+            Some(alignment) => alignment,
+            None => { return None; }
+        }
+    } else {
+        let needle: Vec<char> = query.as_str().chars().collect();
+        edit_alignment(&needle, &haystack, case_sensitive, initials_only)
+    };
+    if typos > budget {
+        return None;
+    }
+    let value = positions.len() as i64 * SCORE_MATCH - typos as i64 * PENALTY_TYPO;
+    Some(Score { value: value, positions: positions, typos: typos })
+}
+
+// Scores a literal match found by the glob, regex and exact modes from where
+// the matched byte range falls in the path: on a boundary and within the file
+// name is best, shorter matches come first for equal placement.
+pub fn span_score(path: &str, start: usize, end: usize) -> Score {
+    let haystack: Vec<char> = path.chars().collect();
+    let first = path[..start].chars().count();
+    let positions: Vec<usize> = (first..first + path[start..end].chars().count()).collect();
+    let basename_start = haystack.iter().rposition(|character| *character == '/').map_or(0, |index| index + 1);
+    let mut value = SCORE_MATCH - positions.len() as i64 * SCORE_GAP;
+    if first < haystack.len() && is_boundary(&haystack, first) {
+        value += BONUS_BOUNDARY;
+    }
+    if first >= basename_start {
+        value += BONUS_BASENAME;
+    }
+    Score { value: value, positions: positions, typos: 0 }
+}
+
+pub fn is_boundary(characters: &[char], index: usize) -> bool {
+    if index == 0 {
+        return true;
+    }
+    let previous = characters[index - 1];
+    let current = characters[index];
+    match previous {
+        '/' | '_' | '-' | '.' | ' ' => true,
+        _ => previous.is_lowercase() && current.is_uppercase(),
+    }
+}
+
+//----------- private -------------//
+
+// Places each segment of the term within a single path component, in the
+// same order as the components, picking the placement with the fewest typos.
+// A trailing `/` only matches directories, so the file name is left out then,
+// and a leading `/` keeps the first segment in the first component.
+fn segmented_alignment(term: &str, haystack: &[char], case_sensitive: bool, initials_only: bool) -> Option<(usize, Vec<usize>)> {
+    let segments: Vec<Vec<char>> = term.split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| segment.chars().collect())
+        .collect();
+    let mut components = vec![];
+    let mut start = 0;
+    for (index, character) in haystack.iter().enumerate() {
+        if *character == '/' {
+            components.push((start, index));
+            start = index + 1;
+        }
+    }
+    if !term.ends_with('/') {
+        components.push((start, haystack.len()));
+    }
+
+    // the best alignment of the segments so far, by the component the last of
+    // them fell in
+    let mut alignments: Vec<Option<(usize, Vec<usize>)>> = vec![Some((0, vec![])); components.len()];
+    for (row, segment) in segments.iter().enumerate() {
+        let mut next_alignments = vec![None; components.len()];
+        let mut best_before: Option<(usize, Vec<usize>)> = None;
+        for (column, &(start, end)) in components.iter().enumerate() {
+            let previous = match row {
+                0 if term.starts_with('/') && column > 0 => None,
+                0 => alignments[column].clone(),
+                _ => best_before.clone(),
+            };
+            if let Some((typos, mut positions)) = previous {
+                let (segment_typos, segment_positions) = edit_alignment(segment, &haystack[start..end], case_sensitive, initials_only);
+                positions.extend(segment_positions.into_iter().map(|position| position + start));
+                next_alignments[column] = Some((typos + segment_typos, positions));
+            }
+            if let Some(ref alignment) = alignments[column] {
+                if best_before.as_ref().map_or(true, |best| alignment.0 < best.0) {
+                    best_before = Some(alignment.clone());
+                }
+            }
+        }
+        alignments = next_alignments;
+    }
+    alignments.into_iter()
+        .filter_map(|alignment| alignment)
+        .min_by_key(|&(typos, _)| typos)
+}
+
+fn edit_alignment(needle: &[char], haystack: &[char], case_sensitive: bool, initials_only: bool) -> (usize, Vec<usize>) {
+    let rows = needle.len() + 1;
+    let columns = haystack.len() + 1;
+    let matches = |row: usize, column: usize| {
+        characters_match(needle[row - 1], haystack[column - 1], case_sensitive) && (!initials_only || is_boundary(haystack, column - 1))
+    };
+    let is_transposition = |row: usize, column: usize| {
+        row > 1 && column > 1 && matches(row, column - 1) && matches(row - 1, column)
+    };
+
+    let mut costs = vec![0; rows * columns];
+    for row in 1..rows {
+        costs[row * columns] = row;
+        for column in 1..columns {
+            let skipped = costs[row * columns + column - 1];
+            let dropped = costs[(row - 1) * columns + column] + 1;
+            let substituted = costs[(row - 1) * columns + column - 1] + if matches(row, column) { 0 } else { 1 };
+            let mut best = skipped.min(dropped).min(substituted);
+            if is_transposition(row, column) {
+                best = best.min(costs[(row - 2) * columns + column - 2] + 1);
+            }
+            costs[row * columns + column] = best;
+        }
+    }
+
+    let mut positions = vec![];
+    let (mut row, mut column) = (rows - 1, columns - 1);
+    while row > 0 && column > 0 {
+        let cost = costs[row * columns + column];
+        if matches(row, column) && cost == costs[(row - 1) * columns + column - 1] {
+            positions.push(column - 1);
+            row -= 1;
+            column -= 1;
+        } else if cost == costs[row * columns + column - 1] {
+            column -= 1;
+        } else if cost == costs[(row - 1) * columns + column - 1] + 1 {
+            row -= 1;
+            column -= 1;
+        } else if is_transposition(row, column) && cost == costs[(row - 2) * columns + column - 2] + 1 {
+            row -= 2;
+            column -= 2;
+        } else {
+            row -= 1;
+        }
+    }
+    positions.reverse();
+    (costs[rows * columns - 1], positions)
+}
+
+fn characters_match(query_character: char, path_character: char, case_sensitive: bool) -> bool {
+    if case_sensitive {
+        query_character == path_character
+    } else {
+        query_character.to_lowercase().eq(path_character.to_lowercase())
+    }
+}
@@ -0,0 +1,47 @@
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use directory_filter::FilteredDirectory;
+
+// The latest published filter result. Cloning is cheap and readers only ever
+// hold the lock for as long as it takes to clone an Arc, so render loops can
+// poll it at their own rate without waiting on a scan.
+#[derive(Clone)]
+pub struct Snapshot {
+    latest: Arc<RwLock<Arc<FilteredDirectory>>>,
+    generation: Arc<AtomicUsize>,
+}
+
+impl Snapshot {
+
+    pub fn new(initial: FilteredDirectory) -> Self {
+        Snapshot {
+            latest: Arc::new(RwLock::new(Arc::new(initial))),
+            generation: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn latest(&self) -> Arc<FilteredDirectory> {
+        match self.latest.read() {
+            Ok(latest) => latest.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    // increases every time a new result is published
+    pub fn generation(&self) -> usize {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    // the result is stamped with its generation, so whoever gets hold of it
+    // later knows which generation it is without asking the snapshot again
+    pub fn publish(&self, filtered_directory: FilteredDirectory) {
+        let mut latest = match self.latest.write() {
+            Ok(latest) => latest,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let generation = self.generation.load(Ordering::SeqCst) + 1;
+        *latest = Arc::new(filtered_directory.with_generation(generation));
+        self.generation.store(generation, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,277 @@
+use std::sync::{Arc, Mutex, MutexGuard, Condvar};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::task::{Poll, Waker};
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+
+use directory_filter::{FilteredDirectory, Snapshot, ResultDelta, Result};
+
+pub type SubscriptionId = usize;
+
+// Fans each published result out to any number of subscribers. Channel
+// subscribers never block the publisher, and every callback subscriber runs on
+// its own thread and only ever sees the newest result, so a slow callback
+// skips results rather than holding up the filter. A callback that panics is
+// dropped. Latest subscribers are polled, e.g. from a future, and are woken
+// without needing a thread of their own.
+#[derive(Clone)]
+pub struct Subscribers {
+    list: Arc<Mutex<SubscriberList>>,
+}
+
+impl Subscribers {
+
+    pub fn new() -> Self {
+        Subscribers { list: Arc::new(Mutex::new(SubscriberList { next_id: 0, subscribers: vec![] })) }
+    }
+
+    pub fn subscribe(&self) -> Result<(SubscriptionId, Receiver<Arc<FilteredDirectory>>)> {
+        let (transmitter, receiver) = channel();
+        let id = try!(self.add(Subscriber::Channel(transmitter)));
+        Ok((id, receiver))
+    }
+
+    // The first delta received goes from an empty list to the latest result in
+    // the snapshot. It is read while holding the subscriber list, so a result
+    // published meanwhile is either in it or delivered after it, never both.
+    pub fn subscribe_deltas(&self, snapshot: &Snapshot) -> Result<(SubscriptionId, Receiver<ResultDelta>)> {
+        let (transmitter, receiver) = channel();
+        let mut list = try!(self.list.lock());
+        let latest = snapshot.latest();
+        let _ = transmitter.send(ResultDelta::between(&[], &latest.file_matches, 0, latest.generation()));
+        Ok((list.push(Subscriber::Delta(transmitter, latest.generation())), receiver))
+    }
+
+    pub fn subscribe_callback<F>(&self, callback: F) -> Result<SubscriptionId> where F: Fn(Arc<FilteredDirectory>) + Send + 'static {
+        self.add(Subscriber::Callback(CallbackWorker::spawn(callback)))
+    }
+
+    pub fn subscribe_latest(&self) -> Result<(SubscriptionId, LatestResult)> {
+        let latest = LatestResult { state: Arc::new(Mutex::new(LatestState { latest: None, waker: None, closed: false })) };
+        let id = try!(self.add(Subscriber::Latest(LatestFeeder { state: latest.state.clone() })));
+        Ok((id, latest))
+    }
+
+    // returns whether there was a subscription to remove
+    pub fn unsubscribe(&self, id: SubscriptionId) -> Result<bool> {
+        let mut list = try!(self.list.lock());
+        let position = list.subscribers.iter().position(|&(subscription_id, _)| subscription_id == id);
+        Ok(position.map(|index| list.subscribers.remove(index)).is_some())
+    }
+
+    // drops every subscription, disconnecting channels and stopping callback
+    // threads
+    pub fn clear(&self) -> Result<()> {
+        try!(self.list.lock()).subscribers.clear();
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.lock().map(|list| list.subscribers.len()).unwrap_or(0)
+    }
+
+    // the delta from the previous result is only worked out when someone has
+    // subscribed to deltas
+    pub fn publish(&self, previous: &FilteredDirectory, filtered_directory: Arc<FilteredDirectory>, generation: usize) -> Result<()> {
+        let mut list = try!(self.list.lock());
+        let wants_delta = list.subscribers.iter().any(|&(_, ref subscriber)| subscriber.wants_delta());
+        let delta = if wants_delta {
+            Some(ResultDelta::between(&previous.file_matches, &filtered_directory.file_matches, generation.saturating_sub(1), generation))
+        } else {
+            None
+        };
+        list.subscribers.retain(|&(_, ref subscriber)| subscriber.deliver(&filtered_directory, &delta, generation));
+        Ok(())
+    }
+
+    //----------- private -------------//
+
+    fn add(&self, subscriber: Subscriber) -> Result<SubscriptionId> {
+        Ok(try!(self.list.lock()).push(subscriber))
+    }
+}
+
+struct SubscriberList {
+    next_id: SubscriptionId,
+    subscribers: Vec<(SubscriptionId, Subscriber)>,
+}
+
+impl SubscriberList {
+
+    fn push(&mut self, subscriber: Subscriber) -> SubscriptionId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.subscribers.push((id, subscriber));
+        id
+    }
+}
+
+enum Subscriber {
+    Channel(Sender<Arc<FilteredDirectory>>),
+    // deltas up to the generation the subscriber started from are skipped
+    Delta(Sender<ResultDelta>, usize),
+    Callback(CallbackWorker),
+    Latest(LatestFeeder),
+}
+
+impl Subscriber {
+
+    fn wants_delta(&self) -> bool {
+        match *self {
+            Subscriber::Delta(_, _) => true,
+            _ => false,
+        }
+    }
+
+    // false once the subscriber has gone away
+    fn deliver(&self, filtered_directory: &Arc<FilteredDirectory>, delta: &Option<ResultDelta>, generation: usize) -> bool {
+        match *self {
+            Subscriber::Channel(ref transmitter) => transmitter.send(filtered_directory.clone()).is_ok(),
+            Subscriber::Delta(ref transmitter, since) => {
+                match *delta {
+                    Some(ref delta) if generation > since => transmitter.send(delta.clone()).is_ok(),
+                    _ => true,
+                }
+            },
+            Subscriber::Callback(ref worker) => worker.deliver(filtered_directory.clone()),
+            Subscriber::Latest(ref feeder) => {
+                feeder.deliver(filtered_directory.clone());
+                true
+            },
+        }
+    }
+}
+
+struct CallbackWorker {
+    state: Arc<(Mutex<CallbackState>, Condvar)>,
+}
+
+struct CallbackState {
+    pending: Option<Arc<FilteredDirectory>>,
+    running: bool,
+}
+
+impl CallbackWorker {
+
+    fn spawn<F>(callback: F) -> Self where F: Fn(Arc<FilteredDirectory>) + Send + 'static {
+        let state = Arc::new((Mutex::new(CallbackState { pending: None, running: true }), Condvar::new()));
+        let local_state = state.clone();
+        thread::spawn(move || {
+            let &(ref lock, ref condvar) = &*local_state;
+            loop {
+                let pending = {
+                    let mut state = match lock.lock() {
+                        Ok(state) => state,
+                        Err(_) => { return; }
+                    };
+                    while state.running && state.pending.is_none() {
+                        state = match condvar.wait(state) {
+                            Ok(state) => state,
+                            Err(_) => { return; }
+                        };
+                    }
+                    if !state.running {
+                        return;
+                    }
+                    state.pending.take()
+                };
+                if let Some(filtered_directory) = pending {
+                    // a panicking callback is unsubscribed on the next publish
+                    // rather than silently swallowing results from then on
+                    if panic::catch_unwind(AssertUnwindSafe(|| callback(filtered_directory))).is_err() {
+                        error!("Filter callback subscriber panicked, unsubscribing it");
+                        if let Ok(mut state) = lock.lock() {
+                            state.running = false;
+                        }
+                        return;
+                    }
+                }
+            }
+        });
+        CallbackWorker { state: state }
+    }
+
+    fn deliver(&self, filtered_directory: Arc<FilteredDirectory>) -> bool {
+        let &(ref lock, ref condvar) = &*self.state;
+        match lock.lock() {
+            Ok(mut state) => {
+                state.pending = Some(filtered_directory);
+                condvar.notify_one();
+                state.running
+            },
+            Err(_) => false,
+        }
+    }
+}
+
+impl Drop for CallbackWorker {
+    fn drop(&mut self) {
+        let &(ref lock, ref condvar) = &*self.state;
+        if let Ok(mut state) = lock.lock() {
+            state.running = false;
+        }
+        condvar.notify_one();
+    }
+}
+
+// The newest result published to a latest subscriber. Polling it takes the
+// result, or registers the waker to be woken when the next one arrives. It is
+// closed once the subscription has been removed.
+pub struct LatestResult {
+    state: Arc<Mutex<LatestState>>,
+}
+
+struct LatestState {
+    latest: Option<Arc<FilteredDirectory>>,
+    waker: Option<Waker>,
+    closed: bool,
+}
+
+impl LatestResult {
+
+    pub fn poll(&self, waker: &Waker) -> Poll<Option<Arc<FilteredDirectory>>> {
+        let mut state = lock_latest(&self.state);
+        if let Some(latest) = state.latest.take() {
+            return Poll::Ready(Some(latest));
+        }
+        if state.closed {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(waker.clone());
+        Poll::Pending
+    }
+}
+
+// held by the subscriber list, so dropping the subscription closes the result
+struct LatestFeeder {
+    state: Arc<Mutex<LatestState>>,
+}
+
+impl LatestFeeder {
+
+    fn deliver(&self, filtered_directory: Arc<FilteredDirectory>) {
+        let mut state = lock_latest(&self.state);
+        state.latest = Some(filtered_directory);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for LatestFeeder {
+    fn drop(&mut self) {
+        let mut state = lock_latest(&self.state);
+        state.closed = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+// the state is only ever swapped whole, so it is still usable after a panic
+fn lock_latest(state: &Mutex<LatestState>) -> MutexGuard<LatestState> {
+    match state.lock() {
+        Ok(state) => state,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
@@ -0,0 +1,181 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
+
+use directory_scanner::{ScannerBuilder, Directory, DirectoryEventBroker};
+use directory_filter::{ContinuousFilter, FilterHandle, FilteredDirectory, SubscriptionId, QueryOptions, MultiSelection, CandidateSource, CandidateList, HistoryStore, Frecency, FilePredicate, RankingSignal, HiddenFilter, IgnoreRules, GitStatus, GitStatusFilter, GitState, Error, Result, project_for};
+
+const QUERY_TIMEOUT_SECONDS: u64 = 30;
+
+// How a root is scanned and which of its files are filtered over at all.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScanSettings {
+    pub threads: Option<usize>,
+    pub hidden: bool,
+    pub ignore: bool,
+    pub git_state: Option<GitState>,
+}
+
+impl Default for ScanSettings {
+    fn default() -> Self {
+        ScanSettings { threads: None, hidden: false, ignore: true, git_state: None }
+    }
+}
+
+// A scanned directory kept warm in memory together with the filter running
+// over it, so repeated queries don't pay for the scan again. An index can also
+// be built over lines read from elsewhere, selections aren't remembered then.
+pub struct Index {
+    project: String,
+    handle: FilterHandle,
+    history: Option<Arc<Mutex<HistoryStore>>>,
+}
+
+impl Index {
+
+    pub fn open(root: &str, settings: &ScanSettings) -> Result<Self> {
+        info!("Indexing {}", root);
+        let (directory, predicates, signals) = try!(scan(root, settings));
+        let project = project_for(&directory);
+
+        let filter = ContinuousFilter::from_directory(directory, DirectoryEventBroker::new());
+        for predicate in predicates {
+            try!(filter.add_predicate(predicate).wait());
+        }
+        for signal in signals {
+            try!(filter.add_ranking_signal(signal).wait());
+        }
+        let history = match HistoryStore::open_default() {
+            Ok(store) => Some(Arc::new(Mutex::new(store))),
+            Err(error) => {
+                warn!("Selection history is disabled: {}", error);
+                None
+            }
+        };
+        if let Some(ref store) = history {
+            try!(filter.add_ranking_signal(Arc::new(Frecency::new(store.clone(), project.clone()))).wait());
+        }
+        let handle = filter.start();
+
+        Ok(Index { project: project, handle: handle, history: history })
+    }
+
+    pub fn for_lines(lines: CandidateList) -> Result<Self> {
+        info!("Indexing lines");
+        let filter = ContinuousFilter::from_candidates(CandidateSource::Lines(lines), DirectoryEventBroker::new());
+        let handle = filter.start();
+        Ok(Index { project: String::new(), handle: handle, history: None })
+    }
+
+    pub fn filter(&self) -> ContinuousFilter {
+        self.handle.filter().clone()
+    }
+
+    pub fn latest(&self) -> Arc<FilteredDirectory> {
+        self.handle.latest()
+    }
+
+    // returns straight away, the results are published to subscribers
+    pub fn set_query(&self, query_string: &str) {
+        self.handle.set_query(query_string.to_string());
+    }
+
+    pub fn set_query_options(&self, options: QueryOptions) -> Result<()> {
+        self.handle.filter().set_query_options(options).wait()
+    }
+
+    pub fn selection(&self) -> MultiSelection {
+        self.handle.selection()
+    }
+
+    pub fn is_processing(&self) -> bool {
+        self.handle.is_processing()
+    }
+
+    pub fn subscribe<F>(&self, callback: F) -> Result<SubscriptionId> where F: Fn(Arc<FilteredDirectory>) + Send + 'static {
+        self.handle.subscribe_callback(callback)
+    }
+
+    pub fn unsubscribe(&self, id: SubscriptionId) -> Result<bool> {
+        self.handle.unsubscribe(id)
+    }
+
+    // remembers the selection so it ranks higher next time
+    pub fn select(&self, query_string: &str, path: &str) -> Result<()> {
+        match self.history {
+            Some(ref store) => try!(store.lock()).record(&self.project, query_string, path),
+            None => Ok(()),
+        }
+    }
+
+    pub fn close(self) -> Result<()> {
+        self.handle.shutdown();
+        self.handle.join()
+    }
+}
+
+// Sends the query and waits for the filter to publish results for it. Takes
+// the filter rather than the index so callers can wait without keeping the
+// index borrowed. Setting a query always publishes a result, and a result for
+// the same query published meanwhile is just as good.
+pub fn query(filter: &ContinuousFilter, query_string: &str) -> Result<Arc<FilteredDirectory>> {
+    let (subscription_id, results) = try!(filter.subscribe());
+    filter.set_query(query_string.to_string());
+    let deadline = Instant::now() + Duration::from_secs(QUERY_TIMEOUT_SECONDS);
+    let result = loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match results.recv_timeout(remaining) {
+            Ok(latest) => {
+                if latest.query().as_str() == query_string {
+                    break Ok(latest);
+                }
+            },
+            Err(RecvTimeoutError::Timeout) => { break Err(Error::Timeout); },
+            Err(RecvTimeoutError::Disconnected) => { break Err(Error::BrokerClosed); },
+        }
+    };
+    try!(filter.unsubscribe(subscription_id));
+    result
+}
+
+// Scans the root and returns the predicates that hide what the settings
+// exclude, along with the ranking signals for it. Files with uncommitted
+// changes rank higher when the root is in a git repository.
+pub fn scan(root: &str, settings: &ScanSettings) -> Result<(Directory, Vec<Arc<dyn FilePredicate>>, Vec<Arc<dyn RankingSignal>>)> {
+    let mut scanner_builder = ScannerBuilder::new();
+    scanner_builder = scanner_builder.start_from_path(root);
+    if let Some(threads) = settings.threads {
+        scanner_builder = scanner_builder.max_threads(threads);
+    }
+    let directory = scanner_builder.build().scan();
+
+    let mut predicates: Vec<Arc<dyn FilePredicate>> = vec![];
+    if !settings.hidden {
+        predicates.push(Arc::new(HiddenFilter::new(root)));
+    }
+    if settings.ignore {
+        let ignore_rules = try!(IgnoreRules::for_root(root));
+        if !ignore_rules.is_empty() {
+            predicates.push(Arc::new(ignore_rules));
+        }
+    }
+
+    let mut signals: Vec<Arc<dyn RankingSignal>> = vec![];
+    match GitStatus::read(Path::new(root)) {
+        Ok(git_status) => {
+            let git_status = Arc::new(git_status);
+            if let Some(state) = settings.git_state {
+                predicates.push(Arc::new(GitStatusFilter::new(git_status.clone(), state)));
+            }
+            signals.push(git_status);
+        },
+        Err(error) => {
+            if settings.git_state.is_some() {
+                return Err(error);
+            }
+            info!("Not ranking by git status: {}", error);
+        }
+    }
+    Ok((directory, predicates, signals))
+}
@@ -3,9 +3,17 @@ extern crate regex;
 extern crate time;
 extern crate crossbeam;
 extern crate scoped_threadpool;
+extern crate unicode_normalization;
+#[cfg(feature = "async")] extern crate futures;
 #[macro_use] extern crate log;
-#[macro_use] extern crate lazy_static;
 
 mod directory_filter;
-pub use directory_filter::{ContinuousFilter,FilteredDirectory, FILTER_EVENT_BROKER};
+pub use directory_filter::{Error, Result};
+pub use directory_filter::{ContinuousFilter,FilteredDirectory, CandidateSource, CandidateList, RegexBuilder, FilterEventBroker, FilterCommand, Shared, Receipt, FilterHandle, Snapshot, Subscribers, SubscriptionId, LatestResult, ResultDelta, DeltaOperation, MultiSelection};
+#[cfg(feature = "async")]
+pub use directory_filter::{QuerySink, ResultStream};
+pub use directory_filter::{Query, QueryOptions, MatchMode, CaseMode, Normalization, Score};
+pub use directory_filter::{Ranking, RankingSignal, HistoryStore, Selection, Frecency, project_for};
+pub use directory_filter::{FilePredicate, GitStatus, GitStatusFilter, GitState, FileStatus, Proximity, IgnoreRules, HiddenFilter};
+pub use directory_filter::{Previewer, PreviewSource, Preview};
 pub use directory_scanner::{ScannerBuilder, Directory, File, DirectoryScanner};
@@ -1,66 +1,188 @@
 extern crate directory_filter;
 extern crate directory_scanner;
-extern crate crossbeam;
+#[macro_use] extern crate serde_json;
+extern crate termion;
+#[macro_use] extern crate log;
 
-use std::io;
-use directory_scanner::ScannerBuilder;
-use std::sync::mpsc::channel;
-use std::sync::{Arc, Mutex};
-use directory_filter::ContinuousFilter;
-use directory_filter::FILTER_EVENT_BROKER;
+mod cli;
+mod index;
+mod rpc;
+mod unix_server;
+mod stdio_server;
+mod picker;
+mod stdin_source;
 
-fn main() {
-    let(trans_new_directory_item, rec_new_directory_item) = channel();
-
-    let mut scanner_builder = ScannerBuilder::new();
-    scanner_builder = scanner_builder.start_from_path("./");
-    //scanner_builder = scanner_builder.max_threads(8);
-    scanner_builder = scanner_builder.update_subscriber(Arc::new(Mutex::new(trans_new_directory_item)));
-    let directory = scanner_builder.build().scan();
-
-    let(trans_filter_match, rec_filter_match) = channel();
+use std::env;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+use directory_filter::{FilteredDirectory, Query, QueryOptions, File, Ranking, CandidateSource, CandidateList, FilterCommand, PreviewSource};
+use cli::{Options, Command, USAGE};
+use rpc::RpcServer;
+use index::Index;
+use picker::{Picker, Outcome};
 
-    let mut filter = ContinuousFilter::new(directory.clone(), Arc::new(Mutex::new(rec_new_directory_item)), Arc::new(Mutex::new(trans_filter_match)));
+const EXIT_MATCHES: i32 = 0;
+const EXIT_NO_MATCHES: i32 = 1;
+const EXIT_ERROR: i32 = 2;
+const EXIT_CANCELLED: i32 = 130;
 
-    crossbeam::scope(|scope| {
+fn main() {
+    let arguments: Vec<String> = env::args().skip(1).collect();
+    let options = match Options::parse(&arguments) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("error: {}\n\n{}", error, USAGE);
+            process::exit(EXIT_ERROR);
+        }
+    };
+    let result = match options.command {
+        Command::Help => {
+            println!("{}", USAGE);
+            Ok(EXIT_MATCHES)
+        },
+        Command::Search if options.query.is_none() => run_picker(&options),
+        Command::Search => run_search(&options),
+        Command::Stdio => run_stdio(&options).map(|_| EXIT_MATCHES),
+        Command::Server(ref socket_path) => run_server(&options, socket_path.clone()).map(|_| EXIT_MATCHES),
+    };
+    match result {
+        Ok(code) => process::exit(code),
+        Err(error) => {
+            eprintln!("error: {}", error);
+            process::exit(EXIT_ERROR);
+        }
+    }
+}
 
-        scope.spawn(move || {
-            filter.start();
-        });
+// without roots, piped input is filtered instead of the current directory
+fn reads_stdin(options: &Options) -> bool {
+    options.roots.is_empty() && !termion::is_tty(&io::stdin())
+}
 
-        let found = rec_filter_match.recv().unwrap();
-        println!("Found {} files", found.len());
-        println!("example = {:?}", found.file_matches.first().unwrap());
+// prints the matches for --query over all roots, best first
+fn run_search(options: &Options) -> Result<i32, String> {
+    let query_options = QueryOptions { match_mode: options.match_mode, ..QueryOptions::default() };
+    let query = Query::with_options(options.query.clone().unwrap_or(String::new()), query_options);
+    if reads_stdin(options) {
+        let lines = try!(stdin_source::read_all().map_err(|error| error.to_string()));
+        let mut filtered_lines = FilteredDirectory::from_candidates(CandidateSource::Lines(lines), query, Ranking::new());
+        try!(filtered_lines.run_filter().map_err(|error| error.to_string()));
+        return print_matches(filtered_lines.into_iter().map(|file| file.as_string()), options.limit);
+    }
+    let mut matches: Vec<(File, Arc<FilteredDirectory>)> = vec![];
+    for root in options.roots() {
+        let (directory, predicates, signals) = try!(index::scan(&root, &options.scan_settings).map_err(|error| error.to_string()));
+        let mut ranking = Ranking::new();
+        for signal in signals {
+            ranking.add_signal(signal);
+        }
+        let mut filtered_directory = FilteredDirectory::with_ranking(directory, query.clone(), ranking).with_predicates(predicates);
+        try!(filtered_directory.run_filter().map_err(|error| error.to_string()));
+        let filtered_directory = Arc::new(filtered_directory);
+        matches.extend(filtered_directory.file_matches.iter().map(|file| (file.clone(), filtered_directory.clone())));
+    }
+    // each root is already sorted, merging only has to rank them against
+    // each other
+    if options.roots().len() > 1 && !query.is_match_all() {
+        let mut ranked: Vec<(usize, i64, String)> = matches.iter().map(|&(ref file, ref filtered_directory)| {
+            let (typos, value) = filtered_directory.score(file).map_or((0, 0), |score| (score.typos, score.value));
+            (typos, value, file.as_string())
+        }).collect();
+        ranked.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)).then(a.2.len().cmp(&b.2.len())).then(a.2.cmp(&b.2)));
+        return print_matches(ranked.into_iter().map(|(_, _, path)| path), options.limit);
+    }
+    print_matches(matches.iter().map(|&(ref file, _)| file.as_string()), options.limit)
+}
 
-        let mut done = false;
-        let mut input = String::new();
-        while !done {
-            match io::stdin().read_line(&mut input) {
-                Ok(_) => {
-                    let last_line = input.lines().last().unwrap();
-                    if input.lines().last().unwrap() == "exit" {
-                        done = true;
-                    } else {
-                        FILTER_EVENT_BROKER.send(last_line.to_string());
-                        let mut keep_looking = true;
-                        while keep_looking {
-                            match rec_filter_match.try_recv() {
-                                Ok(found) => {
-                                    println!("matches = {}", found.len());
-                                    //println!("first = {:?}", found.matches);
-                                },
-                                Err(_) => { keep_looking = false }
-                            }
-                        }
-                    }
+// the picker works on the first root only, or on piped lines as they arrive.
+// Lines are only previewed with a --preview command.
+fn run_picker(options: &Options) -> Result<i32, String> {
+    let (index, preview) = if reads_stdin(options) {
+        let lines = CandidateList::new();
+        let index = try!(Index::for_lines(lines.clone()).map_err(|error| error.to_string()));
+        let filter = index.filter();
+        stdin_source::stream(lines, move || filter.send(FilterCommand::LinesAppended));
+        let preview = match options.preview {
+            Some(PreviewSource::Command(ref command)) => Some(PreviewSource::Command(command.clone())),
+            _ => None,
+        };
+        (index, preview)
+    } else {
+        let root = options.roots().remove(0);
+        let index = try!(Index::open(&root, &options.scan_settings).map_err(|error| error.to_string()));
+        (index, options.preview.clone())
+    };
+    let query_options = QueryOptions { match_mode: options.match_mode, ..QueryOptions::default() };
+    try!(index.set_query_options(query_options).map_err(|error| error.to_string()));
+    let mut picker = Picker::new(&index);
+    if let Some(source) = preview {
+        picker = picker.with_preview(source);
+    }
+    let outcome = picker.run();
+    let code = match outcome {
+        Ok(Outcome::Selected { query, paths }) => {
+            for path in &paths {
+                if let Err(error) = index.select(&query, path) {
+                    warn!("Failed to record selection of {}: {}", path, error);
                 }
-                Err(error) => { println!("error: {}", error); }
             }
+            print_matches(paths.into_iter(), None)
+        },
+        Ok(Outcome::Cancelled) => Ok(EXIT_CANCELLED),
+        Err(error) => Err(error.to_string()),
+    };
+    try!(index.close().map_err(|error| error.to_string()));
+    code
+}
+
+fn print_matches<I>(paths: I, limit: Option<usize>) -> Result<i32, String> where I: Iterator<Item=String> {
+    let stdout = io::stdout();
+    let mut output = stdout.lock();
+    let mut found = false;
+    for path in paths.take(limit.unwrap_or(usize::max_value())) {
+        found = true;
+        if let Err(error) = writeln!(output, "{}", path) {
+            // the reader went away, e.g. `| head`
+            if error.kind() == io::ErrorKind::BrokenPipe {
+                break;
+            }
+            return Err(error.to_string());
         }
-        //println!("total files in directory: {}", directory.len());
-        //println!("Finished");
+    }
+    Ok(if found { EXIT_MATCHES } else { EXIT_NO_MATCHES })
+}
+
+// stdout belongs to the protocol, so failures are reported on stderr
+fn run_stdio(options: &Options) -> Result<(), String> {
+    let server = rpc_server(options);
+    try!(server.open_root(&options.roots().remove(0)).map_err(|error| error.to_string()));
+    stdio_server::serve(Arc::new(server)).map_err(|error| error.to_string())
+}
+
+fn run_server(options: &Options, socket_path: Option<PathBuf>) -> Result<(), String> {
+    let server = rpc_server(options);
+    if let Some(root) = options.roots.first() {
+        try!(server.open_root(root).map_err(|error| error.to_string()));
+    }
+    let socket_path = socket_path.unwrap_or_else(default_socket_path);
+    unix_server::serve(&socket_path, Arc::new(server)).map_err(|error| error.to_string())
+}
+
+// --mode and --limit become the defaults for every request
+fn rpc_server(options: &Options) -> RpcServer {
+    let server = RpcServer::new(options.scan_settings.clone()).match_mode(options.match_mode);
+    match options.limit {
+        Some(limit) => server.limit(limit),
+        None => server,
+    }
+}
 
-        FILTER_EVENT_BROKER.close();
-        drop(scanner_builder);
-    });
+// $XDG_RUNTIME_DIR/directory_filter.sock, falling back to the temp directory
+fn default_socket_path() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(ref dir) if !dir.is_empty() => PathBuf::from(dir).join("directory_filter.sock"),
+        _ => env::temp_dir().join(format!("directory_filter-{}.sock", env::var("USER").unwrap_or(String::from("user")))),
+    }
 }
@@ -0,0 +1,334 @@
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use termion;
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::screen::IntoAlternateScreen;
+use termion::{clear, color, cursor, style};
+
+use directory_filter::{FilteredDirectory, MultiSelection, Previewer, PreviewSource, Preview, Result};
+use index::Index;
+
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+const TICK_MS: u64 = 100;
+const HEADER_LINES: u16 = 2;
+const DEFAULT_TERMINAL_SIZE: (u16, u16) = (80, 24);
+const PREVIEW_LINES: usize = 256;
+const MIN_PREVIEW_WIDTH: u16 = 60;
+
+enum Event {
+    Key(Key),
+    Results(Arc<FilteredDirectory>),
+    Preview(Preview),
+    InputClosed,
+}
+
+pub enum Outcome {
+    Selected { query: String, paths: Vec<String> },
+    Cancelled,
+}
+
+// A full screen picker over an index, drawn on the terminal rather than
+// stdout so the selection can still be piped on.
+pub struct Picker<'a> {
+    index: &'a Index,
+    selection: MultiSelection,
+    query: String,
+    latest: Arc<FilteredDirectory>,
+    cursor: usize,
+    offset: usize,
+    page_size: usize,
+    spinner: usize,
+    drawn_processing: bool,
+    drawn_total: usize,
+    preview_source: Option<PreviewSource>,
+    previewer: Option<Previewer>,
+    previewed_path: Option<String>,
+    preview: Option<Preview>,
+}
+
+impl<'a> Picker<'a> {
+
+    pub fn new(index: &'a Index) -> Self {
+        Picker {
+            index: index,
+            selection: index.selection(),
+            query: String::new(),
+            latest: index.latest(),
+            cursor: 0,
+            offset: 0,
+            page_size: 1,
+            spinner: 0,
+            drawn_processing: false,
+            drawn_total: 0,
+            preview_source: None,
+            previewer: None,
+            previewed_path: None,
+            preview: None,
+        }
+    }
+
+    // shows the highlighted path next to the results on wide enough terminals
+    pub fn with_preview(mut self, source: PreviewSource) -> Self {
+        self.preview_source = Some(source);
+        self
+    }
+
+    pub fn run(mut self) -> Result<Outcome> {
+        let tty = try!(termion::get_tty());
+        let input = try!(tty.try_clone());
+        let mut screen = try!(try!(tty.into_raw_mode()).into_alternate_screen());
+
+        let (events, receiver) = channel();
+        let key_events = events.clone();
+        // blocks on the terminal until the process exits, there is no way to
+        // interrupt the read
+        thread::spawn(move || {
+            for key in input.keys() {
+                match key {
+                    Ok(key) => {
+                        if key_events.send(Event::Key(key)).is_err() {
+                            return;
+                        }
+                    },
+                    Err(_) => { break; }
+                }
+            }
+            let _ = key_events.send(Event::InputClosed);
+        });
+        if let Some(source) = self.preview_source.clone() {
+            let preview_events = events.clone();
+            self.previewer = Some(Previewer::new(source, PREVIEW_LINES, move |preview| {
+                let _ = preview_events.send(Event::Preview(preview));
+            }));
+        }
+        let subscription = try!(self.index.subscribe(move |filtered_directory| {
+            let _ = events.send(Event::Results(filtered_directory));
+        }));
+
+        let outcome = self.event_loop(&mut screen, &receiver);
+        let _ = write!(screen, "{}", style::Reset);
+        let _ = screen.flush();
+        try!(self.index.unsubscribe(subscription));
+        outcome
+    }
+
+    //----------- private -------------//
+
+    fn event_loop<W: Write>(&mut self, screen: &mut W, events: &Receiver<Event>) -> Result<Outcome> {
+        self.update_preview();
+        try!(self.render(screen));
+        loop {
+            match events.recv_timeout(Duration::from_millis(TICK_MS)) {
+                Ok(Event::Key(key)) => {
+                    if let Some(outcome) = self.handle_key(key) {
+                        return Ok(outcome);
+                    }
+                },
+                Ok(Event::Results(filtered_directory)) => { self.set_results(filtered_directory); },
+                Ok(Event::Preview(preview)) => { self.preview = Some(preview); },
+                Ok(Event::InputClosed) | Err(RecvTimeoutError::Disconnected) => { return Ok(Outcome::Cancelled); },
+                // ticks only redraw to turn or clear the spinner, or when piped
+                // lines grew the total without changing the matches
+                Err(RecvTimeoutError::Timeout) => {
+                    if self.index.is_processing() {
+                        self.spinner += 1;
+                    } else if !self.drawn_processing && self.latest.total_len() == self.drawn_total {
+                        continue;
+                    }
+                },
+            }
+            self.update_preview();
+            try!(self.render(screen));
+        }
+    }
+
+    // asks for a preview whenever the highlighted path changes, which also
+    // abandons the one still being read
+    fn update_preview(&mut self) {
+        let current = self.latest.file_matches.get(self.cursor).map(|file| file.as_string());
+        if current == self.previewed_path {
+            return;
+        }
+        if let Some(ref previewer) = self.previewer {
+            match current {
+                Some(ref path) => previewer.request(path),
+                None => previewer.cancel(),
+            }
+        }
+        self.previewed_path = current;
+    }
+
+    fn handle_key(&mut self, key: Key) -> Option<Outcome> {
+        match key {
+            // everything selected, or the highlighted path when nothing is
+            Key::Char('\n') => {
+                if !self.selection.is_empty() {
+                    return Some(Outcome::Selected { query: self.query.clone(), paths: self.selection.paths() });
+                }
+                return self.latest.file_matches.get(self.cursor).map(|file| {
+                    Outcome::Selected { query: self.query.clone(), paths: vec![file.as_string()] }
+                });
+            },
+            Key::Char('\t') => {
+                self.toggle_current();
+                self.move_cursor(1);
+            },
+            Key::BackTab => {
+                self.toggle_current();
+                self.move_cursor(-1);
+            },
+            Key::Alt('a') => { self.selection.select_all(&self.latest); },
+            Key::Alt('i') => { self.selection.invert(&self.latest); },
+            Key::Alt('d') => { self.selection.clear(); },
+            Key::Esc | Key::Ctrl('c') | Key::Ctrl('g') | Key::Ctrl('d') => { return Some(Outcome::Cancelled); },
+            Key::Up | Key::Ctrl('p') | Key::Ctrl('k') => { self.move_cursor(-1); },
+            Key::Down | Key::Ctrl('n') => { self.move_cursor(1); },
+            Key::PageUp => { let page = self.page_size as isize; self.move_cursor(-page); },
+            Key::PageDown => { let page = self.page_size as isize; self.move_cursor(page); },
+            Key::Backspace => {
+                if self.query.pop().is_some() {
+                    self.query_changed();
+                }
+            },
+            Key::Ctrl('u') => {
+                self.query.clear();
+                self.query_changed();
+            },
+            Key::Ctrl('w') => {
+                let trimmed_length = self.query.trim_end().len();
+                self.query.truncate(trimmed_length);
+                let word_start = self.query.rfind(char::is_whitespace).map_or(0, |index| index + 1);
+                self.query.truncate(word_start);
+                self.query_changed();
+            },
+            Key::Char(character) if !character.is_control() => {
+                self.query.push(character);
+                self.query_changed();
+            },
+            _ => {}
+        }
+        None
+    }
+
+    fn toggle_current(&mut self) {
+        if let Some(file) = self.latest.file_matches.get(self.cursor) {
+            self.selection.toggle(&file.as_string());
+        }
+    }
+
+    fn query_changed(&mut self) {
+        self.index.set_query(&self.query);
+        self.cursor = 0;
+        self.offset = 0;
+    }
+
+    fn set_results(&mut self, filtered_directory: Arc<FilteredDirectory>) {
+        self.latest = filtered_directory;
+        let last = self.latest.len().saturating_sub(1);
+        if self.cursor > last {
+            self.cursor = last;
+        }
+    }
+
+    fn move_cursor(&mut self, rows: isize) {
+        let last = self.latest.len().saturating_sub(1) as isize;
+        let cursor = (self.cursor as isize + rows).max(0).min(last);
+        self.cursor = cursor as usize;
+    }
+
+    fn render<W: Write>(&mut self, screen: &mut W) -> Result<()> {
+        let (width, height) = termion::terminal_size().unwrap_or(DEFAULT_TERMINAL_SIZE);
+        let rows = height.saturating_sub(HEADER_LINES) as usize;
+        self.page_size = rows.max(1);
+        // keep the cursor on screen
+        if self.cursor < self.offset {
+            self.offset = self.cursor;
+        } else if self.cursor >= self.offset + self.page_size {
+            self.offset = self.cursor + 1 - self.page_size;
+        }
+
+        try!(write!(screen, "{}{}", clear::All, cursor::Goto(1, 2)));
+        self.drawn_processing = self.index.is_processing();
+        let progress = if self.drawn_processing {
+            SPINNER[self.spinner % SPINNER.len()]
+        } else {
+            ' '
+        };
+        self.drawn_total = self.latest.total_len();
+        try!(write!(screen, "{}{} {}/{}", color::Fg(color::LightBlack), progress, self.latest.len(), self.drawn_total));
+        if !self.selection.is_empty() {
+            try!(write!(screen, " ({} selected)", self.selection.len()));
+        }
+        try!(write!(screen, "{}", color::Fg(color::Reset)));
+
+        let list_width = if self.previewer.is_some() && width >= MIN_PREVIEW_WIDTH { width / 2 } else { width };
+        let available = (list_width as usize).saturating_sub(2);
+        for (row, file) in self.latest.file_matches.iter().enumerate().skip(self.offset).take(rows) {
+            let path = file.as_string();
+            let positions = self.latest.score(file).map(|score| score.positions).unwrap_or(vec![]);
+            let line = highlighted_line(&path, &positions, available);
+            try!(write!(screen, "{}", cursor::Goto(1, (row - self.offset) as u16 + HEADER_LINES + 1)));
+            let marker = if self.selection.is_selected(&path) { '*' } else { ' ' };
+            if row == self.cursor {
+                try!(write!(screen, "{}>{}{}{}", style::Invert, marker, line, style::Reset));
+            } else {
+                try!(write!(screen, " {}{}", marker, line));
+            }
+        }
+
+        if list_width < width {
+            try!(self.render_preview(screen, list_width + 1, width - list_width - 1, height));
+        }
+
+        let prompt_width = self.query.chars().count() as u16 + 3;
+        try!(write!(screen, "{}> {}{}", cursor::Goto(1, 1), self.query, cursor::Goto(prompt_width, 1)));
+        try!(screen.flush());
+        Ok(())
+    }
+
+    // a separator column followed by the preview, blank until the preview for
+    // the highlighted path has been read
+    fn render_preview<W: Write>(&self, screen: &mut W, column: u16, width: u16, height: u16) -> Result<()> {
+        let lines: &[String] = match self.preview {
+            Some(ref preview) if Some(&preview.path) == self.previewed_path.as_ref() => &preview.lines[..],
+            _ => &[],
+        };
+        let available = width.saturating_sub(2) as usize;
+        for row in HEADER_LINES + 1..height + 1 {
+            try!(write!(screen, "{}{}│{}", cursor::Goto(column, row), color::Fg(color::LightBlack), color::Fg(color::Reset)));
+            if let Some(line) = lines.get((row - HEADER_LINES - 1) as usize) {
+                let visible: String = line.chars().take(available).collect();
+                try!(write!(screen, " {}", visible));
+            }
+        }
+        Ok(())
+    }
+}
+
+//----------- private -------------//
+
+// paths that don't fit lose their start rather than the file name
+fn highlighted_line(path: &str, positions: &[usize], width: usize) -> String {
+    let characters: Vec<char> = path.chars().collect();
+    let mut line = String::new();
+    let skip = if characters.len() > width {
+        line.push_str("..");
+        characters.len() - width.saturating_sub(2)
+    } else {
+        0
+    };
+    for (index, character) in characters.iter().enumerate().skip(skip) {
+        if positions.binary_search(&index).is_ok() {
+            line.push_str(&format!("{}{}{}", color::Fg(color::Yellow), character, color::Fg(color::Reset)));
+        } else {
+            line.push(*character);
+        }
+    }
+    line
+}
@@ -0,0 +1,339 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+
+use serde_json::{self, Value};
+
+use directory_filter::{FilteredDirectory, SubscriptionId, QueryOptions, MatchMode, GitState, Error};
+use index::{self, Index, ScanSettings};
+
+const DEFAULT_LIMIT: usize = 100;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+const NO_ROOT: i64 = -32000;
+
+// Answers requests against a single warm index. The server knows nothing about
+// the transport, every connection hands it one request per line and gets back
+// the line to reply with, encoded in the connection's framing.
+pub struct RpcServer {
+    index: Mutex<Option<OpenIndex>>,
+    next_index_id: AtomicUsize,
+    settings: ScanSettings,
+    match_mode: MatchMode,
+    limit: usize,
+    running: AtomicBool,
+}
+
+// Subscription ids are only unique within one index, so they are kept
+// together with the id of the index they were made on.
+struct OpenIndex {
+    id: usize,
+    index: Index,
+}
+
+// JsonRpc speaks JSON-RPC 2.0, Frames tags every line with a "type" of
+// response, results or error instead, which is simpler for editor plugins to
+// route. Frames also reports errors for requests sent without an id.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Framing {
+    JsonRpc,
+    Frames,
+}
+
+// Per connection state, notifications for subscriptions are written to the
+// connection through the sender.
+pub struct Connection {
+    writer: Sender<String>,
+    framing: Framing,
+    subscriptions: Arc<Mutex<Vec<(usize, SubscriptionId)>>>,
+}
+
+#[derive(Clone)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcServer {
+
+    pub fn new(settings: ScanSettings) -> Self {
+        RpcServer {
+            index: Mutex::new(None),
+            next_index_id: AtomicUsize::new(0),
+            settings: settings,
+            match_mode: MatchMode::Fuzzy,
+            limit: DEFAULT_LIMIT,
+            running: AtomicBool::new(true),
+        }
+    }
+
+    // how every root opened from now on matches queries
+    pub fn match_mode(mut self, match_mode: MatchMode) -> Self {
+        self.match_mode = match_mode;
+        self
+    }
+
+    // how many matches are sent when a request doesn't ask for a limit
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    // scans the root and serves it instead of the previous one, returning how
+    // many files it has
+    pub fn open_root(&self, root: &str) -> Result<usize, Error> {
+        self.open_root_with(root, &self.settings)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    // None for notifications, which never get a response
+    pub fn handle_message(&self, connection: &Connection, message: &str) -> Option<String> {
+        let framing = connection.framing;
+        let request: Value = match serde_json::from_str(message) {
+            Ok(request) => request,
+            Err(error) => {
+                return Some(framing.error(Value::Null, RpcError::new(PARSE_ERROR, &error.to_string())));
+            }
+        };
+        let id = request.get("id").cloned();
+        let result = match request.get("method").and_then(Value::as_str) {
+            Some(method) => {
+                let params = request.get("params").cloned().unwrap_or(Value::Null);
+                self.dispatch(connection, method, &params)
+            },
+            None => Err(RpcError::new(INVALID_REQUEST, "request has no method")),
+        };
+        match (id, result) {
+            (Some(id), Ok(result)) => Some(framing.response(id, result)),
+            (Some(id), Err(error)) => Some(framing.error(id, error)),
+            (None, Err(ref error)) if framing == Framing::Frames => Some(framing.error(Value::Null, error.clone())),
+            (None, _) => None,
+        }
+    }
+
+    // drops the connection's subscriptions once it has gone away, those made
+    // on an index that has since been replaced went with it
+    pub fn disconnect(&self, connection: &Connection) {
+        let subscriptions = match connection.subscriptions.lock() {
+            Ok(mut subscriptions) => subscriptions.drain(..).collect::<Vec<_>>(),
+            Err(_) => { return; }
+        };
+        if let Ok(open_index) = self.index.lock() {
+            if let Some(ref open_index) = *open_index {
+                for (index_id, id) in subscriptions {
+                    if index_id != open_index.id {
+                        continue;
+                    }
+                    if let Err(error) = open_index.index.unsubscribe(id) {
+                        warn!("Failed to unsubscribe {}: {}", id, error);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn close(&self) {
+        self.running.store(false, Ordering::SeqCst);
+        let index = match self.index.lock() {
+            Ok(mut index) => index.take(),
+            Err(_) => None,
+        };
+        if let Some(open_index) = index {
+            if let Err(error) = open_index.index.close() {
+                error!("Index failed to shut down cleanly: {}", error);
+            }
+        }
+    }
+
+    //----------- private -------------//
+
+    fn dispatch(&self, connection: &Connection, method: &str, params: &Value) -> Result<Value, RpcError> {
+        info!("Handling rpc request {}", method);
+        match method {
+            "setRoot" => self.set_root(params),
+            "query" => self.query(params),
+            "subscribe" => self.subscribe(connection, params),
+            "select" => self.select(params),
+            "shutdown" => {
+                self.running.store(false, Ordering::SeqCst);
+                Ok(Value::Bool(true))
+            },
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, &format!("unknown method {}", method))),
+        }
+    }
+
+    // subscriptions on the previous root end with it, clients subscribe again.
+    // "git" narrows the root down to staged, modified or untracked files.
+    fn set_root(&self, params: &Value) -> Result<Value, RpcError> {
+        let root = try!(string_param(params, "root"));
+        let mut settings = self.settings.clone();
+        settings.git_state = try!(git_state_param(params, self.settings.git_state));
+        let total = try!(self.open_root_with(&root, &settings));
+        Ok(json!({ "root": root, "total": total }))
+    }
+
+    // waits on the filter without holding the index, so other requests are
+    // answered in the meantime
+    fn query(&self, params: &Value) -> Result<Value, RpcError> {
+        let query_string = try!(string_param(params, "query"));
+        let limit = try!(limit_param(params, self.limit));
+        let filter = {
+            let open_index = try!(self.index.lock().map_err(Error::from));
+            try!(open_index.as_ref().ok_or(RpcError::new(NO_ROOT, "no root has been set"))).index.filter()
+        };
+        let filtered_directory = try!(index::query(&filter, &query_string));
+        Ok(results(&filtered_directory, limit))
+    }
+
+    // results are pushed as "results" notifications every time they change
+    fn subscribe(&self, connection: &Connection, params: &Value) -> Result<Value, RpcError> {
+        let limit = try!(limit_param(params, self.limit));
+        let open_index = try!(self.index.lock().map_err(Error::from));
+        let open_index = try!(open_index.as_ref().ok_or(RpcError::new(NO_ROOT, "no root has been set")));
+        let index = &open_index.index;
+        let writer = Mutex::new(connection.writer.clone());
+        let framing = connection.framing;
+        let id = try!(index.subscribe(move |filtered_directory| {
+            let notification = framing.results(results(&filtered_directory, limit));
+            if let Ok(writer) = writer.lock() {
+                let _ = writer.send(notification);
+            }
+        }));
+        try!(connection.subscriptions.lock().map_err(Error::from)).push((open_index.id, id));
+        Ok(json!({ "subscription": id }))
+    }
+
+    fn select(&self, params: &Value) -> Result<Value, RpcError> {
+        let path = try!(string_param(params, "path"));
+        let query_string = params.get("query").and_then(Value::as_str).unwrap_or("").to_string();
+        let open_index = try!(self.index.lock().map_err(Error::from));
+        let open_index = try!(open_index.as_ref().ok_or(RpcError::new(NO_ROOT, "no root has been set")));
+        try!(open_index.index.select(&query_string, &path));
+        Ok(Value::Bool(true))
+    }
+
+    fn open_root_with(&self, root: &str, settings: &ScanSettings) -> Result<usize, Error> {
+        let new_index = try!(Index::open(root, settings));
+        try!(new_index.set_query_options(QueryOptions { match_mode: self.match_mode, ..QueryOptions::default() }));
+        let total = new_index.latest().total_len();
+        let previous = {
+            let mut index = try!(self.index.lock());
+            let id = self.next_index_id.fetch_add(1, Ordering::SeqCst);
+            ::std::mem::replace(&mut *index, Some(OpenIndex { id: id, index: new_index }))
+        };
+        match previous {
+            Some(previous) => previous.index.close().map(|_| total),
+            None => Ok(total),
+        }
+    }
+
+}
+
+impl Connection {
+
+    pub fn new(writer: Sender<String>) -> Self {
+        Connection::with_framing(writer, Framing::JsonRpc)
+    }
+
+    pub fn with_framing(writer: Sender<String>, framing: Framing) -> Self {
+        Connection { writer: writer, framing: framing, subscriptions: Arc::new(Mutex::new(vec![])) }
+    }
+}
+
+impl Framing {
+
+    fn response(&self, id: Value, result: Value) -> String {
+        match *self {
+            Framing::JsonRpc => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Framing::Frames => json!({ "type": "response", "id": id, "result": result }),
+        }.to_string()
+    }
+
+    fn error(&self, id: Value, error: RpcError) -> String {
+        match *self {
+            Framing::JsonRpc => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": error.code, "message": error.message },
+            }),
+            Framing::Frames => json!({ "type": "error", "id": id, "code": error.code, "message": error.message }),
+        }.to_string()
+    }
+
+    // results frames carry the generation they were produced for, so a client
+    // can drop frames older than a response it already has
+    fn results(&self, results: Value) -> String {
+        match *self {
+            Framing::JsonRpc => json!({ "jsonrpc": "2.0", "method": "results", "params": results }).to_string(),
+            Framing::Frames => {
+                let mut frame = results;
+                if let Value::Object(ref mut fields) = frame {
+                    fields.insert(String::from("type"), Value::from("results"));
+                }
+                frame.to_string()
+            }
+        }
+    }
+}
+
+impl RpcError {
+
+    fn new(code: i64, message: &str) -> Self {
+        RpcError { code: code, message: message.to_string() }
+    }
+}
+
+impl From<Error> for RpcError {
+
+    fn from(error: Error) -> Self {
+        let code = match error {
+            Error::InvalidQuery(_) => INVALID_PARAMS,
+            _ => INTERNAL_ERROR,
+        };
+        RpcError::new(code, &error.to_string())
+    }
+}
+
+//----------- private -------------//
+
+fn results(filtered_directory: &FilteredDirectory, limit: usize) -> Value {
+    let matches: Vec<Value> = filtered_directory.file_matches.iter().take(limit).map(|file| {
+        let positions = filtered_directory.score(file).map(|score| score.positions).unwrap_or(vec![]);
+        json!({ "path": file.as_string(), "positions": positions })
+    }).collect();
+    json!({
+        "generation": filtered_directory.generation(),
+        "query": filtered_directory.query().as_str(),
+        "matched": filtered_directory.len(),
+        "total": filtered_directory.total_len(),
+        "matches": matches,
+    })
+}
+
+fn string_param(params: &Value, name: &str) -> Result<String, RpcError> {
+    params.get(name).and_then(Value::as_str).map(String::from)
+        .ok_or(RpcError::new(INVALID_PARAMS, &format!("missing string param {}", name)))
+}
+
+fn limit_param(params: &Value, default_limit: usize) -> Result<usize, RpcError> {
+    match params.get("limit") {
+        None | Some(&Value::Null) => Ok(default_limit),
+        Some(limit) => limit.as_u64().map(|limit| limit as usize)
+            .ok_or(RpcError::new(INVALID_PARAMS, "limit must be a positive integer")),
+    }
+}
+
+fn git_state_param(params: &Value, default_state: Option<GitState>) -> Result<Option<GitState>, RpcError> {
+    match params.get("git") {
+        None | Some(&Value::Null) => Ok(default_state),
+        Some(state) => state.as_str().and_then(GitState::from_name).map(Some)
+            .ok_or(RpcError::new(INVALID_PARAMS, "git must be staged, modified or untracked")),
+    }
+}
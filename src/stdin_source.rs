@@ -0,0 +1,93 @@
+use std::io::{self, BufRead};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use directory_filter::CandidateList;
+
+const NOTIFY_INTERVAL_MS: u64 = 100;
+
+// reads every line of stdin before returning
+pub fn read_all() -> io::Result<CandidateList> {
+    let lines = CandidateList::new();
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut batch = vec![];
+    while let Some(line) = try!(read_line(&mut input)) {
+        batch.push(line);
+    }
+    lines.extend(batch);
+    lines.finish();
+    Ok(lines)
+}
+
+// Streams stdin into the list in the background. `notify` is called at most
+// every NOTIFY_INTERVAL_MS while lines keep arriving, once lines stop arriving
+// for that long and once more when stdin closes.
+pub fn stream<F>(lines: CandidateList, notify: F) -> JoinHandle<()> where F: Fn() + Send + 'static {
+    let (transmitter, receiver) = channel();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        let mut input = stdin.lock();
+        loop {
+            match read_line(&mut input) {
+                Ok(Some(line)) => {
+                    if transmitter.send(line).is_err() {
+                        return;
+                    }
+                },
+                Ok(None) => { return; },
+                Err(error) => {
+                    warn!("Stopped reading stdin: {}", error);
+                    return;
+                }
+            }
+        }
+    });
+
+    thread::spawn(move || {
+        let interval = Duration::from_millis(NOTIFY_INTERVAL_MS);
+        let mut batch = vec![];
+        let mut last_notified = Instant::now();
+        loop {
+            match receiver.recv_timeout(interval) {
+                Ok(line) => {
+                    batch.push(line);
+                    if last_notified.elapsed() < interval {
+                        continue;
+                    }
+                },
+                Err(RecvTimeoutError::Timeout) => {
+                    if batch.is_empty() {
+                        continue;
+                    }
+                },
+                Err(RecvTimeoutError::Disconnected) => { break; }
+            }
+            lines.extend(batch.drain(..).collect());
+            notify();
+            last_notified = Instant::now();
+        }
+        lines.extend(batch);
+        lines.finish();
+        notify();
+    })
+}
+
+//----------- private -------------//
+
+// None once the input ends. Paths aren't always valid UTF-8, so invalid bytes
+// are replaced rather than ending the input.
+fn read_line<R: BufRead>(input: &mut R) -> io::Result<Option<String>> {
+    let mut buffer = vec![];
+    if try!(input.read_until(b'\n', &mut buffer)) == 0 {
+        return Ok(None);
+    }
+    if buffer.last() == Some(&b'\n') {
+        buffer.pop();
+        if buffer.last() == Some(&b'\r') {
+            buffer.pop();
+        }
+    }
+    Ok(Some(String::from_utf8_lossy(&buffer).into_owned()))
+}
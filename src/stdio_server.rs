@@ -0,0 +1,52 @@
+use std::io::{self, BufRead, Write};
+use std::sync::Arc;
+use std::sync::mpsc::channel;
+use std::thread;
+
+use rpc::{RpcServer, Connection, Framing};
+
+// Serves line delimited JSON frames on stdin/stdout for editor plugins that
+// spawn the binary, until stdin closes or a shutdown request arrives.
+pub fn serve(server: Arc<RpcServer>) -> io::Result<()> {
+    let (writer, lines) = channel::<String>();
+    let writer_thread = thread::spawn(move || {
+        let stdout = io::stdout();
+        for line in lines {
+            let mut output = stdout.lock();
+            if writeln!(output, "{}", line).and_then(|_| output.flush()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let connection = Connection::with_framing(writer.clone(), Framing::Frames);
+    let stdin = io::stdin();
+    let mut result = Ok(());
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(error) => {
+                result = Err(error);
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = server.handle_message(&connection, &line) {
+            if writer.send(response).is_err() {
+                break;
+            }
+        }
+        if !server.is_running() {
+            break;
+        }
+    }
+
+    server.disconnect(&connection);
+    server.close();
+    drop(connection);
+    drop(writer);
+    let _ = writer_thread.join();
+    result
+}
@@ -0,0 +1,98 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+
+use rpc::{RpcServer, Connection};
+
+const ACCEPT_POLL_INTERVAL_MS: u64 = 50;
+
+// Serves newline delimited JSON-RPC on a unix socket until a client asks the
+// server to shut down.
+pub fn serve(socket_path: &Path, server: Arc<RpcServer>) -> io::Result<()> {
+    try!(remove_stale_socket(socket_path));
+    let listener = try!(UnixListener::bind(socket_path));
+    // non blocking so the accept loop notices a shutdown request
+    try!(listener.set_nonblocking(true));
+    info!("Listening on {:?}", socket_path);
+
+    let mut result = Ok(());
+    while server.is_running() {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let local_server = server.clone();
+                thread::spawn(move || {
+                    if let Err(error) = handle_connection(stream, local_server) {
+                        warn!("Connection closed with an error: {}", error);
+                    }
+                });
+            },
+            Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(ACCEPT_POLL_INTERVAL_MS));
+            },
+            Err(error) => {
+                result = Err(error);
+                break;
+            }
+        }
+    }
+
+    server.close();
+    if let Err(error) = fs::remove_file(socket_path) {
+        warn!("Failed to remove socket {:?}: {}", socket_path, error);
+    }
+    result
+}
+
+//----------- private -------------//
+
+fn handle_connection(stream: UnixStream, server: Arc<RpcServer>) -> io::Result<()> {
+    try!(stream.set_nonblocking(false));
+    let mut write_stream = try!(stream.try_clone());
+    let (writer, lines) = channel::<String>();
+    let writer_thread = thread::spawn(move || {
+        for line in lines {
+            if writeln!(write_stream, "{}", line).and_then(|_| write_stream.flush()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let connection = Connection::new(writer.clone());
+    for line in BufReader::new(stream).lines() {
+        let line = try!(line);
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = server.handle_message(&connection, &line) {
+            if writer.send(response).is_err() {
+                break;
+            }
+        }
+        if !server.is_running() {
+            break;
+        }
+    }
+
+    server.disconnect(&connection);
+    drop(connection);
+    drop(writer);
+    let _ = writer_thread.join();
+    Ok(())
+}
+
+// a socket file left behind by a crashed server is removed, but we refuse to
+// take over from one that is still answering
+fn remove_stale_socket(socket_path: &Path) -> io::Result<()> {
+    if !socket_path.exists() {
+        return Ok(());
+    }
+    match UnixStream::connect(socket_path) {
+        Ok(_) => Err(io::Error::new(io::ErrorKind::AddrInUse, format!("a server is already listening on {:?}", socket_path))),
+        Err(_) => fs::remove_file(socket_path),
+    }
+}
@@ -1,76 +1,905 @@
 extern crate directory_filter;
 extern crate directory_scanner;
-extern crate time;
-extern crate crossbeam;
+#[macro_use] extern crate serde_json;
+#[cfg(feature = "async")]
+extern crate futures;
 
 
-use directory_scanner::ScannerBuilder;
-use time::Tm;
+use directory_scanner::{ScannerBuilder, DirectoryEventBroker};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::process::{self, Command, Stdio};
 use std::sync::mpsc::channel;
 use std::sync::{Arc, Mutex};
-use directory_filter::{SimpleFilter,ContinuousFilter};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde_json::Value;
+use directory_filter::{ContinuousFilter,RegexBuilder,FilteredDirectory,Query,QueryOptions,FilterEventBroker,FilterCommand,Snapshot,Subscribers,MatchMode,CaseMode,Normalization,IgnoreRules,MultiSelection,CandidateList,CandidateSource,FilePredicate,Ranking,RankingSignal,Proximity,HistoryStore,Previewer,PreviewSource,ResultDelta,DeltaOperation,GitStatus,GitState,File,Error};
 
 
 #[test]
-fn simple_filtering_example() {
+fn advanced_filtering_example() {
+
+    let mut scanner_builder = ScannerBuilder::new();
+    scanner_builder = scanner_builder.start_from_path("tests/fixture_dir/");
+    scanner_builder = scanner_builder.max_threads(1);
+    let directory = scanner_builder.build().scan();
+
+    let(trans_filter_match, rec_filter_match) = channel();
+
+    let filter = ContinuousFilter::new(directory, Arc::new(Mutex::new(trans_filter_match)), DirectoryEventBroker::new());
+
+    let handle = filter.start();
+    filter.set_query("file-1".to_string());
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut done = false;
+    while !done {
+        let found = rec_filter_match.recv_timeout(Duration::from_secs(5)).unwrap().len();
+        if found == 2 || Instant::now() >= deadline {
+            assert_eq!(found, 2);
+            done = true;
+        }
+    }
+
+    handle.shutdown();
+    assert!(handle.join().is_ok());
+}
+
+#[test]
+fn query_segments_match_path_components_in_order() {
+    let regex = RegexBuilder::new("dir/fi".to_string()).build().unwrap();
+
+    assert!(regex.is_match("src/directory_filter/filtered_directory.rs"));
+    assert!(regex.is_match("src/directory_filter/nested/filter_event_broker.rs"));
+    assert!(!regex.is_match("src/directory_filter.rs"));
+    assert!(!regex.is_match("fixtures/dir-file"));
+}
+
+#[test]
+fn leading_slash_anchors_the_query_below_the_scanned_root() {
+    let regex = RegexBuilder::new("/dir/fi".to_string()).build().unwrap();
+    assert!(regex.is_match("directory_filter/filtered_directory.rs"));
+    assert!(!regex.is_match("src/directory_filter/filtered_directory.rs"));
+
+    let filtered_directory = filter_sample("spaces", "/main", QueryOptions::default());
+    assert_eq!(file_names(&filtered_directory.file_matches), vec!["main rs"]);
+    let file = &filtered_directory.file_matches[0];
+    let path: Vec<char> = file.as_string().chars().collect();
+    let highlighted: String = filtered_directory.score(file).unwrap().positions.iter().map(|&position| path[position]).collect();
+    assert_eq!(highlighted, "main");
+}
+
+#[test]
+fn initials_queries_match_across_a_slash() {
+    let options = QueryOptions { match_mode: MatchMode::Initials, ..QueryOptions::default() };
+    let filtered_directory = filter_sample("typo_segments", "d/f", options);
+    assert_eq!(file_names(&filtered_directory.file_matches), vec!["filtered_directory.rs"]);
+}
+
+#[test]
+fn glob_queries_match_file_names_or_whole_paths() {
+    let regex = RegexBuilder::new("*.rs".to_string()).match_mode(MatchMode::Glob).build().unwrap();
+
+    assert!(regex.is_match("src/directory_filter/query.rs"));
+    assert!(!regex.is_match("src/directory_filter/query.rs.orig"));
+
+    let regex = RegexBuilder::new("src/*.rs".to_string()).match_mode(MatchMode::Glob).build().unwrap();
+    assert!(regex.is_match("src/lib.rs"));
+    assert!(!regex.is_match("src/directory_filter/query.rs"));
+}
+
+#[test]
+fn globs_with_a_slash_match_below_the_scanned_root() {
+    let mut scanner_builder = ScannerBuilder::new();
+    scanner_builder = scanner_builder.start_from_path("tests/");
+    scanner_builder = scanner_builder.max_threads(1);
+    let directory = scanner_builder.build().scan();
+
+    let options = QueryOptions { match_mode: MatchMode::Glob, ..QueryOptions::default() };
+    let mut filtered_directory = FilteredDirectory::new(directory, Query::with_options("fixture_dir/file-1*".to_string(), options));
+    filtered_directory.run_filter().unwrap();
+
+    let paths: Vec<String> = filtered_directory.file_matches.iter().map(|file| file.as_string()).collect();
+    assert_eq!(paths.len(), 2);
+    assert!(paths[0].ends_with("fixture_dir/file-1"));
+    assert!(paths[1].ends_with("fixture_dir/file-10"));
+
+    let positions = filtered_directory.score(&filtered_directory.file_matches[0]).unwrap().positions;
+    assert_eq!(positions.last(), Some(&(paths[0].chars().count() - 1)));
+}
+
+#[test]
+fn ignore_rules_skip_ignored_paths_below_the_root() {
+    let patterns = vec!["target/".to_string(), "*.log".to_string(), "!keep.log".to_string()];
+    let ignore_rules = IgnoreRules::from_patterns("project", &patterns).unwrap();
+
+    assert!(ignore_rules.is_ignored("project/target/debug/build"));
+    assert!(ignore_rules.is_ignored("project/logs/server.log"));
+    assert!(!ignore_rules.is_ignored("project/logs/keep.log"));
+    assert!(!ignore_rules.is_ignored("project/src/target"));
+}
+
+#[test]
+fn ignore_rules_read_nested_ignore_files_below_the_root() {
+    let root = env::temp_dir().join(format!("directory_filter-ignore-{}", process::id()));
+    let _ = fs::remove_dir_all(&root);
+    for directory in &["sub/generated", "other/generated", "build"] {
+        fs::create_dir_all(root.join(directory)).unwrap();
+    }
+    fs::write(root.join(".gitignore"), "*.log\nbuild/\n").unwrap();
+    fs::write(root.join("sub/.ignore"), "!keep.log\n/generated/\n").unwrap();
+    fs::write(root.join("build/.gitignore"), "!*\n").unwrap();
+
+    let ignore_rules = IgnoreRules::for_root(&root.to_string_lossy()).unwrap();
+    let ignored = |path: &str| ignore_rules.is_ignored(&root.join(path).to_string_lossy());
+    let _ = fs::remove_dir_all(&root);
+    assert!(ignored("server.log"));
+    assert!(ignored("sub/server.log"));
+    assert!(!ignored("sub/keep.log"));
+    assert!(ignored("other/keep.log"));
+    assert!(ignored("sub/generated/parser.rs"));
+    assert!(!ignored("other/generated/parser.rs"));
+    assert!(ignored("build/main.rs"));
+}
+
+#[test]
+fn git_status_reads_staged_modified_untracked_and_renamed_files() {
+    let root = env::temp_dir().join(format!("directory_filter-git-{}", process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    let git = |arguments: &[&str]| {
+        let status = Command::new("git")
+            .args(&["-c", "user.name=test", "-c", "user.email=test@example.com", "-c", "commit.gpgsign=false", "-C"])
+            .arg(&root)
+            .args(arguments)
+            .stdout(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+    git(&["init", "-q"]);
+    for name in &["clean.rs", "modified.rs", "old name.rs"] {
+        fs::write(root.join(name), name).unwrap();
+    }
+    git(&["add", "."]);
+    git(&["commit", "-q", "-m", "initial"]);
+    fs::write(root.join("modified.rs"), "changed").unwrap();
+    fs::write(root.join("staged.rs"), "staged").unwrap();
+    git(&["add", "staged.rs"]);
+    git(&["mv", "old name.rs", "new name.rs"]);
+    fs::write(root.join("untracked.rs"), "untracked").unwrap();
+
+    let git_status = GitStatus::read(&root).unwrap();
+    let path = |name: &str| root.join(name).to_string_lossy().into_owned();
+    let _ = fs::remove_dir_all(&root);
+    assert!(git_status.is(&path("staged.rs"), GitState::Staged));
+    assert!(git_status.is(&path("modified.rs"), GitState::Modified));
+    assert!(!git_status.is(&path("modified.rs"), GitState::Staged));
+    assert!(git_status.is(&path("untracked.rs"), GitState::Untracked));
+    assert!(git_status.is(&path("new name.rs"), GitState::Staged));
+    assert!(git_status.status(&path("old name.rs")).is_none());
+    assert!(git_status.status(&path("clean.rs")).is_none());
+
+    let (code, _, stderr) = run_binary(&["--query", "file", "--git", "nope", "tests/fixture_dir/"]);
+    assert_eq!(code, 2);
+    assert!(stderr.contains("unknown git state nope"));
+}
+
+#[test]
+fn proximity_ignores_test_directories_above_both_paths() {
+    let proximity = Proximity::new("/home/tests/project/src/parser.rs");
+    let query = Query::new("parser".to_string());
+    let counterpart = proximity.boost(&query, "/home/tests/project/src/parser_test.rs");
+    let sibling = proximity.boost(&query, "/home/tests/project/src/lexer_test.rs");
+
+    assert!(counterpart > sibling);
+    assert!(proximity.boost(&query, "/home/tests/project/tests/parser.rs") > proximity.boost(&query, "/home/tests/project/tests/lexer.rs"));
+}
+
+#[test]
+fn proximity_ranks_the_counterpart_of_the_current_file_first() {
+    let lines = CandidateList::new();
+    lines.extend(vec!["lib/foo.rs".to_string(), "src/foo.rs".to_string(), "src/foo_test.rs".to_string(), "src/food.rs".to_string()]);
+    let mut ranking = Ranking::new();
+    ranking.add_signal(Arc::new(Proximity::new("src/foo.rs")));
+
+    let mut filtered_lines = FilteredDirectory::from_candidates(CandidateSource::Lines(lines), Query::new("foo".to_string()), ranking);
+    filtered_lines.run_filter().unwrap();
+    assert_eq!(filtered_lines.file_matches[0].as_string(), "src/foo_test.rs");
+}
+
+#[test]
+fn filtered_directory_ranks_best_matches_first() {
     let mut scanner_builder = ScannerBuilder::new();
     scanner_builder = scanner_builder.start_from_path("tests/fixture_dir/");
     scanner_builder = scanner_builder.max_threads(1);
     let directory = scanner_builder.build().scan();
 
-    let filter = SimpleFilter::new(&directory, "file-1");
-    let filtered_directory = filter.execute();
+    let mut filtered_directory = FilteredDirectory::new(directory, Query::new("file-1".to_string()));
+    filtered_directory.run_filter().unwrap();
+
+    let paths: Vec<String> = filtered_directory.into_iter().map(|file| file.as_string()).collect();
+    assert_eq!(paths.len(), 2);
+    assert!(paths[0].ends_with("file-1"));
+    assert!(paths[1].ends_with("file-10"));
+}
+
+#[test]
+fn multi_selection_keeps_paths_that_still_match() {
+    let mut scanner_builder = ScannerBuilder::new();
+    scanner_builder = scanner_builder.start_from_path("tests/fixture_dir/");
+    scanner_builder = scanner_builder.max_threads(1);
+    let directory = scanner_builder.build().scan();
+
+    let mut filtered_directory = FilteredDirectory::new(directory, Query::new("file-1".to_string()));
+    filtered_directory.run_filter().unwrap();
+    let selection = MultiSelection::new();
+    selection.select_all(&filtered_directory);
+    assert_eq!(selection.len(), 2);
+
+    filtered_directory.re_filter(Query::new("file-10".to_string())).unwrap();
+    selection.retain_matching(&filtered_directory);
+    assert_eq!(selection.len(), 1);
+    assert!(selection.paths()[0].ends_with("file-10"));
+
+    selection.invert(&filtered_directory);
+    assert!(selection.is_empty());
+}
+
+#[test]
+fn filtered_directory_filters_arbitrary_lines() {
+    let lines = CandidateList::new();
+    lines.extend(vec!["src/main.rs".to_string(), "README.md".to_string(), "docs/maintainers".to_string()]);
+
+    let mut filtered_lines = FilteredDirectory::from_candidates(CandidateSource::Lines(lines.clone()), Query::new("main".to_string()), Ranking::new());
+    filtered_lines.run_filter().unwrap();
+    assert_eq!(filtered_lines.len(), 2);
+    assert_eq!(filtered_lines.file_matches[0].as_string(), "src/main.rs");
+
+    lines.push("main");
+    filtered_lines.run_filter().unwrap();
+    assert_eq!(filtered_lines.total_len(), 4);
+    assert_eq!(filtered_lines.file_matches[0].as_string(), "main");
+}
+
+#[test]
+fn appended_lines_are_merged_into_the_matches_in_rank_order() {
+    let lines = CandidateList::new();
+    lines.extend(vec!["src/main.rs".to_string(), "README.md".to_string(), "docs/maintainers".to_string()]);
+    let mut filtered_lines = FilteredDirectory::from_candidates(CandidateSource::Lines(lines.clone()), Query::new("main".to_string()), Ranking::new());
+    filtered_lines.run_filter().unwrap();
+
+    lines.extend(vec!["main".to_string(), "other".to_string(), "lib/main_test.rs".to_string(), "zzz/main.rs".to_string()]);
+    filtered_lines.filter_appended().unwrap();
+    let mut refiltered = FilteredDirectory::from_candidates(CandidateSource::Lines(lines.clone()), Query::new("main".to_string()), Ranking::new());
+    refiltered.run_filter().unwrap();
+    assert_eq!(filtered_lines.len(), 5);
+    assert_eq!(paths(&filtered_lines.file_matches), paths(&refiltered.file_matches));
+
+    let filter = ContinuousFilter::from_candidates(CandidateSource::Lines(lines.clone()), DirectoryEventBroker::new());
+    let handle = filter.start();
+    assert_eq!(handle.latest().len(), 7);
+    lines.push("a");
+    filter.event_broker().send_with_receipt(FilterCommand::LinesAppended).wait().unwrap();
+    assert_eq!(handle.latest().len(), 8);
+    assert_eq!(handle.latest().file_matches[0].as_string(), "a");
+
+    handle.shutdown();
+    assert!(handle.join().is_ok());
+}
+
+#[test]
+fn typo_tolerance_keeps_the_match_mode_rules() {
+    let options = QueryOptions { match_mode: MatchMode::Initials, typo_budget: 1, ..QueryOptions::default() };
+    let filtered_directory = filter_sample("typo_initials", "fdi", options);
+    assert_eq!(file_names(&filtered_directory.file_matches), vec!["filtered_directory_iterator.rs"]);
+
+    let options = QueryOptions { typo_budget: 1, ..QueryOptions::default() };
+    let filtered_directory = filter_sample("typo_segments", "dir/fi", options);
+    assert_eq!(filtered_directory.len(), 1);
+    assert!(filtered_directory.file_matches[0].as_string().ends_with("directory_filter/filtered_directory.rs"));
+}
+
+#[test]
+fn word_boundaries_rank_initials_first() {
+    let filtered_directory = filter_sample("initials", "fdii", QueryOptions::default());
+    assert_eq!(file_names(&filtered_directory.file_matches), vec!["FilteredDirectoryIntoIterator.rs", "fiddling_info.rs"]);
+
+    let options = QueryOptions { match_mode: MatchMode::Initials, ..QueryOptions::default() };
+    let filtered_directory = filter_sample("initials", "fdii", options);
+    assert_eq!(file_names(&filtered_directory.file_matches), vec!["FilteredDirectoryIntoIterator.rs"]);
+}
+
+#[test]
+fn smart_case_respects_case_once_the_query_has_an_uppercase_letter() {
+    let query = Query::new("\u{dc}ber".to_string());
+    assert!(query.is_case_sensitive());
+    assert!(query.regex().unwrap().is_match("docs/\u{dc}ber.md"));
+    assert!(!query.regex().unwrap().is_match("docs/\u{fc}ber.md"));
 
+    let query = Query::new("\u{fc}ber".to_string());
+    assert!(!query.is_case_sensitive());
+    assert!(query.regex().unwrap().is_match("docs/\u{dc}BER.md"));
+
+    let options = QueryOptions { case_mode: CaseMode::Respect, ..QueryOptions::default() };
+    assert!(!Query::with_options("\u{fc}ber".to_string(), options).regex().unwrap().is_match("docs/\u{dc}BER.md"));
+
+    let options = QueryOptions { case_mode: CaseMode::Ignore, ..QueryOptions::default() };
+    assert!(Query::with_options("\u{dc}ber".to_string(), options).regex().unwrap().is_match("docs/\u{fc}ber.md"));
+}
+
+#[test]
+fn normalization_matches_composed_and_decomposed_names() {
+    let composed = "caf\u{e9}.png";
+    let filtered_directory = filter_sample("unicode", "caf\u{e9}", QueryOptions::default());
+    assert_eq!(file_names(&filtered_directory.file_matches), vec![composed]);
+
+    let options = QueryOptions { normalization: Normalization::Nfc, ..QueryOptions::default() };
+    let filtered_directory = filter_sample("unicode", "caf\u{e9}", options);
     assert_eq!(filtered_directory.len(), 2);
+
+    let options = QueryOptions { fold_diacritics: true, ..QueryOptions::default() };
+    let filtered_directory = filter_sample("unicode", "cafe", options);
+    assert_eq!(filtered_directory.len(), 2);
+    // positions point into the path as it was given, the accent of a
+    // decomposed name isn't highlighted
+    for file in filtered_directory.file_matches.iter() {
+        let path: Vec<char> = file.as_string().chars().collect();
+        let highlighted: String = filtered_directory.score(file).unwrap().positions.iter().map(|&position| path[position]).collect();
+        let expected = if file.as_string().ends_with(composed) { "caf\u{e9}" } else { "cafe" };
+        assert_eq!(highlighted, expected);
+    }
 }
 
 #[test]
-fn advanced_filtering_example() {
+fn typo_budget_allows_approximate_matches() {
+    let filtered_directory = filter_sample("typos", "qzery", QueryOptions::default());
+    assert!(filtered_directory.file_matches.is_empty());
+
+    let options = QueryOptions { typo_budget: 1, ..QueryOptions::default() };
+    let filtered_directory = filter_sample("typos", "qzery", options);
+    assert_eq!(file_names(&filtered_directory.file_matches), vec!["query.rs"]);
+    assert_eq!(filtered_directory.score(&filtered_directory.file_matches[0]).unwrap().typos, 1);
+}
+
+#[test]
+fn approximate_matches_count_at_least_one_typo() {
+    let options = QueryOptions { typo_budget: 1, ..QueryOptions::default() };
+    let filtered_directory = filter_sample("spaces", "main rs", options);
+    assert_eq!(file_names(&filtered_directory.file_matches), vec!["main rs", "main.rs"]);
+
+    let typos: Vec<usize> = filtered_directory.file_matches.iter().map(|file| filtered_directory.score(file).unwrap().typos).collect();
+    assert_eq!(typos, vec![0, 1]);
+}
+
+#[test]
+fn typo_tolerance_matches_a_space_like_any_other_character() {
+    let options = QueryOptions { typo_budget: 1, ..QueryOptions::default() };
+    let filtered_directory = filter_sample("spaces", "rs main", options);
+    assert!(filtered_directory.file_matches.is_empty());
+}
+
+#[test]
+fn result_deltas_turn_the_previous_generation_into_the_current_one() {
+    let cases: Vec<(Vec<&str>, Vec<&str>)> = vec![
+        (vec!["file-1", "file-2", "file-3"], vec!["file-3", "file-1", "file-4"]),
+        (vec![], vec!["file-5", "file-5"]),
+        (vec!["file-1", "file-1", "file-2"], vec!["file-1"]),
+        (vec!["file-1"], vec!["file-1", "file-1"]),
+        (vec!["file-2", "file-1", "file-1"], vec!["file-1", "file-2", "file-1", "file-3"]),
+        (vec!["file-1", "file-2"], vec![]),
+    ];
+    for (previous, current) in cases {
+        let previous = fixture_files(&previous);
+        let current = fixture_files(&current);
+        let delta = ResultDelta::between(&previous, &current, 1, 2);
+        let mut applied = previous.clone();
+        delta.apply(&mut applied);
+        assert_eq!(paths(&applied), paths(&current));
+        assert_eq!(delta.total_len, current.len());
+    }
+
+    let unchanged = fixture_files(&["file-1", "file-1"]);
+    assert!(ResultDelta::between(&unchanged, &unchanged, 1, 2).is_empty());
 
-    let(trans_new_directory_item, rec_new_directory_item) = channel();
+    // only the files out of place are sent, and never more than the whole list
+    let previous = fixture_files(&["file-1", "file-2", "file-3", "file-4", "file-5", "file-6"]);
+    let swapped = fixture_files(&["file-1", "file-2", "file-4", "file-3", "file-5", "file-6"]);
+    assert_eq!(ResultDelta::between(&previous, &swapped, 1, 2).operations, vec![DeltaOperation::Reordered { start: 2, files: fixture_files(&["file-4", "file-3"]) }]);
+    let mut reversed = previous.clone();
+    reversed.reverse();
+    reversed.push(fixture_files(&["file-7"]).remove(0));
+    let delta = ResultDelta::between(&previous, &reversed, 1, 2);
+    let carried: usize = delta.operations.iter().map(|operation| match *operation {
+        DeltaOperation::Added { ref files, .. } | DeltaOperation::Reordered { ref files, .. } => files.len(),
+        DeltaOperation::Removed { .. } => 0,
+    }).sum();
+    assert_eq!(carried, reversed.len());
+    let mut applied = previous.clone();
+    delta.apply(&mut applied);
+    assert_eq!(paths(&applied), paths(&reversed));
+}
 
+#[test]
+fn delta_subscribers_start_from_the_latest_result() {
     let mut scanner_builder = ScannerBuilder::new();
     scanner_builder = scanner_builder.start_from_path("tests/fixture_dir/");
     scanner_builder = scanner_builder.max_threads(1);
-    scanner_builder = scanner_builder.update_subscriber(Arc::new(Mutex::new(trans_new_directory_item)));
     let directory = scanner_builder.build().scan();
+    let filter = ContinuousFilter::from_directory(directory, DirectoryEventBroker::new());
+    let handle = filter.start();
 
-    let(trans_filter_change, rec_filter_change) = channel();
-    let(trans_filter_match, rec_filter_match) = channel();
+    let (_, deltas) = handle.subscribe_deltas().unwrap();
+    let mut files = vec![];
+    let baseline = deltas.recv_timeout(Duration::from_secs(5)).unwrap();
+    baseline.apply(&mut files);
+    assert_eq!(files.len(), 10);
+    assert_eq!(baseline.generation, handle.snapshot().generation());
+
+    handle.set_query("file-1".to_string());
+    let mut generation = baseline.generation;
+    while files.len() != 2 {
+        let delta = deltas.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(delta.previous_generation, generation);
+        generation = delta.generation;
+        delta.apply(&mut files);
+    }
+    assert_eq!(paths(&files), paths(&handle.latest().file_matches));
+
+    handle.shutdown();
+    assert!(handle.join().is_ok());
+}
+
+#[test]
+fn filter_event_broker_coalesces_consecutive_queries() {
+    let broker = FilterEventBroker::new();
+    broker.send(FilterCommand::SetQuery("f".to_string()));
+    broker.send(FilterCommand::SetQuery("fi".to_string()));
+    broker.send(FilterCommand::Pause);
+    broker.send(FilterCommand::SetQuery("fil".to_string()));
+    broker.close();
+
+    assert_eq!(broker.recv().unwrap(), FilterCommand::SetQuery("fi".to_string()));
+    assert_eq!(broker.recv().unwrap(), FilterCommand::Pause);
+    assert_eq!(broker.recv().unwrap(), FilterCommand::SetQuery("fil".to_string()));
+    assert!(broker.recv().is_err());
+}
+
+#[test]
+fn filter_event_broker_debounces_queries_only() {
+    let broker = FilterEventBroker::with_debounce(Duration::from_millis(50));
+    let sent_at = Instant::now();
+    broker.send(FilterCommand::SetQuery("f".to_string()));
+
+    assert!(broker.next_ready_in().unwrap() > Duration::from_millis(0));
+    assert_eq!(broker.try_recv(), None);
+    assert!(match broker.recv_timeout(Duration::from_millis(1)) { Err(Error::Timeout) => true, _ => false });
+    assert_eq!(broker.recv().unwrap(), FilterCommand::SetQuery("f".to_string()));
+    assert!(sent_at.elapsed() >= Duration::from_millis(50));
+    assert_eq!(broker.next_ready_in(), None);
+
+    broker.send(FilterCommand::Pause);
+    assert!(broker.is_ready());
+    assert_eq!(broker.try_recv(), Some(FilterCommand::Pause));
+
+    broker.close();
+    assert!(match broker.recv_deadline(Instant::now() + Duration::from_secs(1)) { Err(Error::BrokerClosed) => true, _ => false });
+}
+
+#[test]
+fn history_store_keeps_selections_across_reopening() {
+    let path = env::temp_dir().join(format!("directory_filter-history-{}", process::id()));
+    let _ = fs::remove_file(&path);
+    {
+        let mut store = HistoryStore::open(path.clone()).unwrap();
+        store.record("project", "query\twith tab", "dir\\with\nnewline.rs").unwrap();
+    }
+    writeln!(OpenOptions::new().append(true).open(&path).unwrap(), "not a selection").unwrap();
+
+    let store = HistoryStore::open(path.clone()).unwrap();
+    let selections = store.selections("project", "dir\\with\nnewline.rs").to_vec();
+    let _ = fs::remove_file(&path);
+    assert_eq!(selections.len(), 1);
+    assert_eq!(selections[0].query, "query\twith tab");
+    assert!(store.selections("other project", "dir\\with\nnewline.rs").is_empty());
+}
+
+#[test]
+fn command_line_options_are_validated() {
+    let (code, stdout, _) = run_binary(&["--query", "file-1", "--limit=1", "tests/fixture_dir/"]);
+    assert_eq!(code, 0);
+    assert_eq!(stdout.lines().count(), 1);
+    assert!(stdout.trim_end().ends_with("file-1"));
+
+    let (code, _, stderr) = run_binary(&["--query", "file", "--mode", "nope", "tests/fixture_dir/"]);
+    assert_eq!(code, 2);
+    assert!(stderr.contains("unknown mode nope"));
+
+    let (code, _, stderr) = run_binary(&["--query", "file", "--limit", "0", "tests/fixture_dir/"]);
+    assert_eq!(code, 2);
+    assert!(stderr.contains("--limit needs a positive number"));
+
+    let (code, _, stderr) = run_binary(&["--socket"]);
+    assert_eq!(code, 2);
+    assert!(stderr.contains("--socket needs a value"));
+
+    let (code, _, stderr) = run_binary(&["--bogus"]);
+    assert_eq!(code, 2);
+    assert!(stderr.contains("unknown option --bogus"));
+
+    let (code, stdout, _) = run_binary(&["--query", "nothing-like-this", "tests/fixture_dir/"]);
+    assert_eq!(code, 1);
+    assert!(stdout.is_empty());
+}
+
+#[test]
+fn filter_changes_are_applied_in_the_order_they_were_sent() {
+    let mut scanner_builder = ScannerBuilder::new();
+    scanner_builder = scanner_builder.start_from_path("tests/fixture_dir/");
+    scanner_builder = scanner_builder.max_threads(1);
+    let directory = scanner_builder.build().scan();
+    let (trans_filter_match, _rec_filter_match) = channel();
+    let filter = ContinuousFilter::new(directory, Arc::new(Mutex::new(trans_filter_match)), DirectoryEventBroker::new());
+    let handle = filter.start();
+
+    let hidden: Arc<dyn FilePredicate> = Arc::new(Suffix("0"));
+    let last: Arc<dyn FilePredicate> = Arc::new(Suffix("-10"));
+    let receipts = vec![
+        filter.add_predicate(hidden.clone()),
+        filter.add_predicate(Arc::new(Suffix("-1"))),
+    ];
+    filter.set_query("file-1".to_string());
+    let receipts = receipts.into_iter().chain(vec![
+        filter.clear_predicates(),
+        filter.add_predicate(hidden.clone()),
+        filter.add_predicate(last.clone()),
+    ]).collect::<Vec<_>>();
+
+    // whatever was published in between, the last change wins
+    for receipt in receipts {
+        receipt.wait().unwrap();
+    }
+    assert_eq!(filter.latest().query().as_str(), "file-1");
+    assert_eq!(file_names(&filter.latest().file_matches), vec!["file-10"]);
+
+    // removing one predicate keeps the others
+    filter.set_query("file".to_string());
+    filter.remove_predicate(last).wait().unwrap();
+    assert_eq!(file_names(&filter.latest().file_matches), vec!["file-10"]);
+    filter.remove_predicate(hidden).wait().unwrap();
+    assert_eq!(filter.latest().len(), 10);
+
+    // changes applied by the running filter still report their errors
+    filter.set_query("(".to_string());
+    let options = QueryOptions { match_mode: MatchMode::Regex, ..QueryOptions::default() };
+    assert!(match filter.set_query_options(options).wait() { Err(Error::InvalidQuery(_)) => true, _ => false });
+
+    handle.shutdown();
+    assert!(handle.join().is_ok());
+}
+
+#[test]
+fn spawned_filters_publish_results_to_subscribers() {
+    let mut scanner_builder = ScannerBuilder::new();
+    scanner_builder = scanner_builder.start_from_path("tests/fixture_dir/");
+    scanner_builder = scanner_builder.max_threads(1);
+    let directory = scanner_builder.build().scan();
+    let filter = ContinuousFilter::from_directory(directory, DirectoryEventBroker::new());
+    let (_, results) = filter.subscribe().unwrap();
+    let handle = filter.spawn();
+    handle.set_query("file-1".to_string());
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut latest = None;
+    while let Ok(result) = results.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        if result.query().as_str() == "file-1" {
+            latest = Some(result);
+            break;
+        }
+    }
+    assert_eq!(file_names(&latest.unwrap().file_matches), vec!["file-1", "file-10"]);
+
+    handle.shutdown();
+    assert!(handle.join().is_ok());
+}
+
+#[test]
+fn snapshot_generations_increase_with_every_published_result() {
+    let snapshot = Snapshot::new(FilteredDirectory::from_candidates(CandidateSource::Lines(CandidateList::new()), Query::new(String::new()), Ranking::new()));
+    let reader = snapshot.clone();
+    assert_eq!(reader.generation(), 0);
+
+    for query in &["a", "b"] {
+        let previous = reader.latest();
+        snapshot.publish(FilteredDirectory::from_candidates(CandidateSource::Lines(CandidateList::new()), Query::new(query.to_string()), Ranking::new()));
+        let latest = reader.latest();
+        assert_eq!(latest.query().as_str(), *query);
+        assert_eq!(latest.generation(), previous.generation() + 1);
+        assert_eq!(reader.generation(), latest.generation());
+    }
+}
+
+#[test]
+fn slow_callback_subscribers_skip_to_the_newest_result() {
+    let subscribers = Subscribers::new();
+    let (started_transmitter, started) = channel();
+    let (release, gate) = channel::<()>();
+    let gate = Mutex::new(gate);
+    subscribers.subscribe_callback(move |result| {
+        let query = result.query().as_str().to_string();
+        started_transmitter.send(query.clone()).unwrap();
+        if query == "a" {
+            gate.lock().unwrap().recv().unwrap();
+        }
+    }).unwrap();
+
+    let mut previous = Arc::new(lines_matching(""));
+    for query in &["a", "b", "c", "d"] {
+        let current = Arc::new(lines_matching(query));
+        subscribers.publish(&previous, current.clone(), 1).unwrap();
+        previous = current;
+        if *query == "a" {
+            assert_eq!(started.recv_timeout(Duration::from_secs(5)).unwrap(), "a");
+        }
+    }
+    release.send(()).unwrap();
+    assert_eq!(started.recv_timeout(Duration::from_secs(5)).unwrap(), "d");
+    assert!(started.recv_timeout(Duration::from_millis(50)).is_err());
+}
+
+#[test]
+fn unsubscribed_and_panicking_subscribers_stop_receiving_results() {
+    let subscribers = Subscribers::new();
+    let (id, results) = subscribers.subscribe().unwrap();
+    subscribers.subscribe_callback(|_| panic!("subscriber failed")).unwrap();
+    assert_eq!(subscribers.len(), 2);
+
+    assert!(subscribers.unsubscribe(id).unwrap());
+    assert!(!subscribers.unsubscribe(id).unwrap());
+    assert_eq!(subscribers.len(), 1);
+
+    let previous = lines_matching("");
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while subscribers.len() > 0 && Instant::now() < deadline {
+        subscribers.publish(&previous, Arc::new(lines_matching("a")), 1).unwrap();
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(subscribers.len(), 0);
+    assert!(results.recv().is_err());
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn result_streams_follow_queries_sent_through_a_sink() {
+    use futures::executor::block_on;
+    use futures::{SinkExt, StreamExt};
+
+    let mut scanner_builder = ScannerBuilder::new();
+    scanner_builder = scanner_builder.start_from_path("tests/fixture_dir/");
+    scanner_builder = scanner_builder.max_threads(1);
+    let directory = scanner_builder.build().scan();
+    let filter = ContinuousFilter::from_directory(directory, DirectoryEventBroker::new());
+    let handle = filter.start();
+    let mut results = filter.result_stream().unwrap();
+    let mut queries = filter.query_sink();
+
+    block_on(queries.send("file-1".to_string())).unwrap();
+    let mut latest = block_on(results.next()).unwrap();
+    while latest.query().as_str() != "file-1" {
+        latest = block_on(results.next()).unwrap();
+    }
+    assert_eq!(file_names(&latest.file_matches), vec!["file-1", "file-10"]);
 
-    let mut filter = ContinuousFilter::new(&directory, Arc::new(Mutex::new(rec_filter_change)), Arc::new(Mutex::new(rec_new_directory_item)), Arc::new(Mutex::new(trans_filter_match)));
-
-    crossbeam::scope(|scope| {
-
-        let finished_transmitter = filter.finished_transmitter.clone();
-        scope.spawn(move || {
-            filter.start();
-        });
-
-        trans_filter_change.send("file-1".to_string()).unwrap();
-
-        let start_time = time::now();
-        let duration = 5;
-        let mut done = false;
-        while !done {
-            let found = rec_filter_match.recv().unwrap().len();
-            if found == 2 {
-                assert_eq!(found, 2);
-                done = true;
-            } else {
-                if time_up(start_time, duration) {
-                    assert_eq!(found, 2);
-                    done = true;
-                }
-            }
+    handle.shutdown();
+    assert!(handle.join().is_ok());
+    assert!(block_on(results.next()).is_none());
+}
+
+#[test]
+fn rpc_server_answers_requests_over_a_unix_socket() {
+    let scratch = env::temp_dir().join(format!("directory_filter-rpc-{}", process::id()));
+    let _ = fs::remove_dir_all(&scratch);
+    fs::create_dir_all(&scratch).unwrap();
+    let socket = scratch.join("socket");
+    let mut server = Command::new(env!("CARGO_BIN_EXE_directory_filter"))
+        .arg(format!("--server={}", socket.display()))
+        .env("XDG_DATA_HOME", &scratch)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let stream = loop {
+        match UnixStream::connect(&socket) {
+            Ok(stream) => break stream,
+            Err(_) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+            Err(error) => panic!("server never listened: {}", error),
+        }
+    };
+    let mut writer = stream.try_clone().unwrap();
+    let mut responses = BufReader::new(stream).lines();
+    let mut call = |id: u64, method: &str, params: Value| -> Value {
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        writeln!(writer, "{}", request).unwrap();
+        let response: Value = serde_json::from_str(&responses.next().unwrap().unwrap()).unwrap();
+        assert_eq!(response["id"], id);
+        response
+    };
+
+    assert_eq!(call(1, "query", json!({ "query": "file" }))["error"]["code"], -32000);
+    assert_eq!(call(2, "setRoot", json!({ "root": "tests/fixture_dir/" }))["result"]["total"], 10);
+    let response = call(3, "query", json!({ "query": "file-1" }));
+    assert_eq!(response["result"]["matched"], 2);
+    let selected = response["result"]["matches"][1]["path"].as_str().unwrap().to_string();
+    assert!(selected.ends_with("file-10"));
+    assert_eq!(call(4, "select", json!({ "query": "file-1", "path": selected }))["result"], true);
+    assert_eq!(call(5, "nope", Value::Null)["error"]["code"], -32601);
+    assert_eq!(call(6, "shutdown", Value::Null)["result"], true);
+
+    assert!(server.wait().unwrap().success());
+    let history = fs::read_to_string(scratch.join("directory_filter").join("history")).unwrap();
+    let _ = fs::remove_dir_all(&scratch);
+    assert!(history.contains(&selected));
+}
+
+#[test]
+fn stdio_server_speaks_typed_frames() {
+    let scratch = env::temp_dir().join(format!("directory_filter-stdio-{}", process::id()));
+    let mut server = Command::new(env!("CARGO_BIN_EXE_directory_filter"))
+        .args(&["--stdio", "tests/fixture_dir/"])
+        .env("XDG_DATA_HOME", &scratch)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut requests = server.stdin.take().unwrap();
+    let mut frames = BufReader::new(server.stdout.take().unwrap()).lines().map(|line| serde_json::from_str::<Value>(&line.unwrap()).unwrap());
+
+    writeln!(requests, "{}", json!({ "id": 1, "method": "query", "params": { "query": "file-1" } })).unwrap();
+    let frame = frames.next().unwrap();
+    assert_eq!(frame["type"], "response");
+    assert_eq!(frame["id"], 1);
+    assert_eq!(frame["result"]["matched"], 2);
+
+    // errors for requests without an id are still reported
+    writeln!(requests, "{}", json!({ "method": "nope" })).unwrap();
+    let frame = frames.next().unwrap();
+    assert_eq!(frame["type"], "error");
+    assert_eq!(frame["id"], Value::Null);
+    assert_eq!(frame["code"], -32601);
+
+    writeln!(requests, "{}", json!({ "id": 2, "method": "subscribe", "params": { "limit": 1 } })).unwrap();
+    assert_eq!(frames.next().unwrap()["id"], 2);
+    writeln!(requests, "{}", json!({ "id": 3, "method": "query", "params": { "query": "file-2" } })).unwrap();
+    let mut answered = false;
+    let mut pushed = false;
+    while !(answered && pushed) {
+        let frame = frames.next().unwrap();
+        match frame["type"].as_str() {
+            Some("response") => {
+                assert_eq!(frame["id"], 3);
+                answered = true;
+            },
+            Some("results") if frame["query"] == "file-2" => {
+                assert_eq!(frame["matches"].as_array().unwrap().len(), 1);
+                assert!(frame["generation"].as_u64().is_some());
+                pushed = true;
+            },
+            _ => {}
         }
-        let _ = finished_transmitter.send(true);
-        drop(trans_filter_change);
-        drop(scanner_builder);
-    });
+    }
+
+    writeln!(requests, "{}", json!({ "id": 4, "method": "shutdown" })).unwrap();
+    assert!(frames.any(|frame| frame["type"] == "response" && frame["id"] == 4 && frame["result"] == true));
+    drop(requests);
+    assert!(server.wait().unwrap().success());
+}
+
+#[test]
+fn previews_are_capped_and_skip_binary_and_special_files() {
+    let scratch = env::temp_dir().join(format!("directory_filter-preview-{}", process::id()));
+    let _ = fs::remove_dir_all(&scratch);
+    fs::create_dir_all(&scratch).unwrap();
+    let text = scratch.join("text.rs");
+    fs::write(&text, format!("fn main() {{\n\tlet long = \"{}\";\n}}\n\x1b[2J", "x".repeat(10000))).unwrap();
+    let binary = scratch.join("binary");
+    fs::write(&binary, b"text\0more").unwrap();
+    let fifo = scratch.join("fifo");
+    assert!(Command::new("mkfifo").arg(&fifo).status().unwrap().success());
+
+    let (transmitter, previews) = channel();
+    let previewer = Previewer::new(PreviewSource::Contents, 3, move |preview| { let _ = transmitter.send(preview); });
+    let preview = |path: &Path| {
+        previewer.request(&path.to_string_lossy());
+        previews.recv_timeout(Duration::from_secs(5)).unwrap().lines
+    };
+    let lines = preview(&text);
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "fn main() {");
+    assert!(lines[1].starts_with("    let long = \"xxx"));
+    assert!(lines[1].len() < 10000);
+    assert_eq!(lines[2], "}");
+    assert_eq!(preview(&binary), vec!["(binary file)"]);
+    assert_eq!(preview(&fifo), vec!["(not a regular file)"]);
+    let _ = fs::remove_dir_all(&scratch);
+}
+
+#[test]
+fn preview_commands_get_the_quoted_path_and_are_dropped_for_newer_requests() {
+    let (transmitter, previews) = channel();
+    let command = "case {} in *slow*) sleep 5;; esac; printf '%s\\n' {}";
+    let previewer = Previewer::new(PreviewSource::Command(command.to_string()), 10, move |preview| { let _ = transmitter.send(preview); });
+
+    let started_at = Instant::now();
+    previewer.request("slow");
+    // long enough for the slow command to have started
+    thread::sleep(Duration::from_millis(100));
+    previewer.request("it's $HOME; `true`");
+    let preview = previews.recv_timeout(Duration::from_secs(4)).unwrap();
+    assert_eq!(preview.path, "it's $HOME; `true`");
+    assert_eq!(preview.lines, vec!["it's $HOME; `true`"]);
+    assert!(started_at.elapsed() < Duration::from_secs(4));
+}
+
+struct Suffix(&'static str);
+
+impl FilePredicate for Suffix {
+    fn accepts(&self, path: &str) -> bool {
+        path.ends_with(self.0)
+    }
+}
+
+fn run_binary(arguments: &[&str]) -> (i32, String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_directory_filter"))
+        .args(arguments)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    (output.status.code().unwrap_or(-1), String::from_utf8_lossy(&output.stdout).into_owned(), String::from_utf8_lossy(&output.stderr).into_owned())
+}
+
+// an unfiltered result over no lines, tagged with the query
+fn lines_matching(query: &str) -> FilteredDirectory {
+    FilteredDirectory::from_candidates(CandidateSource::Lines(CandidateList::new()), Query::new(query.to_string()), Ranking::new())
+}
+
+// the files below tests/samples/<sample>/ matching the query
+fn filter_sample(sample: &str, query: &str, options: QueryOptions) -> FilteredDirectory {
+    let mut scanner_builder = ScannerBuilder::new();
+    scanner_builder = scanner_builder.start_from_path(&format!("tests/samples/{}/", sample));
+    scanner_builder = scanner_builder.max_threads(1);
+    let directory = scanner_builder.build().scan();
+
+    let mut filtered_directory = FilteredDirectory::new(directory, Query::with_options(query.to_string(), options));
+    filtered_directory.run_filter().unwrap();
+    filtered_directory
+}
+
+// the fixture_dir files with the given names, in that order
+fn fixture_files(names: &[&str]) -> Vec<File> {
+    let mut scanner_builder = ScannerBuilder::new();
+    scanner_builder = scanner_builder.start_from_path("tests/fixture_dir/");
+    scanner_builder = scanner_builder.max_threads(1);
+    let directory = scanner_builder.build().scan();
+
+    let mut filtered_directory = FilteredDirectory::new(directory, Query::new(String::new()));
+    filtered_directory.run_filter().unwrap();
+    names.iter().map(|name| {
+        filtered_directory.file_matches.iter().find(|file| file.as_string().ends_with(&format!("/{}", name))).unwrap().clone()
+    }).collect()
+}
+
+fn paths(files: &[File]) -> Vec<String> {
+    files.iter().map(|file| file.as_string()).collect()
 }
 
-fn time_up(start_time: Tm, duration: i64) -> bool {
-    let difference = time::now().to_timespec().sec - start_time.to_timespec().sec;
-    difference > duration
+fn file_names(files: &[File]) -> Vec<String> {
+    files.iter().map(|file| {
+        Path::new(&file.as_string()).file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned())
+    }).collect()
 }